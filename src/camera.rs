@@ -0,0 +1,160 @@
+use bevy::prelude::*;
+use bevy::render::camera::ScalingMode;
+use bevy::transform::TransformSystem;
+
+use crate::fog::RoomVisibility;
+use crate::main_menu::GameState;
+use crate::map_gen::RoomTag;
+use crate::player::Player;
+use crate::tilemap::TILE_SIZE;
+
+// How fast trauma wears off, per second
+const TRAUMA_DECAY: f32 = 1.5;
+// How far the camera moves at full trauma
+const MAX_SHAKE_OFFSET: f32 = 6.;
+
+pub struct GameCameraPlugin;
+
+impl Plugin for GameCameraPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<CameraSettings>()
+            .add_event::<ShakeCamera>()
+            .add_systems(Startup, spawn_camera)
+            .add_systems(
+                Update,
+                update_projection.run_if(resource_changed::<CameraSettings>()),
+            )
+            .add_systems(
+                PostUpdate,
+                (follow_player, shake_camera)
+                    .chain()
+                    .run_if(in_state(GameState::Game))
+                    .before(TransformSystem::TransformPropagate),
+            )
+            .register_type::<CameraSettings>()
+            .register_type::<GameCamera>();
+    }
+}
+
+#[derive(Resource, Reflect)]
+#[reflect(Resource)]
+pub struct CameraSettings {
+    // Smallest area of the world that's always in view, see `ScalingMode::AutoMin`
+    pub min_width: f32,
+    pub min_height: f32,
+    // The player can move this far from the middle of the screen before the camera follows
+    pub dead_zone: Vec2,
+    // How quickly the camera catches up, higher is snappier
+    pub smoothing: f32,
+    // Stops the camera showing past the edges of the room the player is in
+    pub clamp_to_room: bool,
+}
+
+impl Default for CameraSettings {
+    fn default() -> Self {
+        CameraSettings {
+            min_width: 256.,
+            min_height: 144.,
+            dead_zone: Vec2::new(24., 16.),
+            smoothing: 8.,
+            clamp_to_room: true,
+        }
+    }
+}
+
+// Shakes the camera. Strength adds up between 0 and 1, and shake grows with its square
+#[derive(Event)]
+pub struct ShakeCamera(pub f32);
+
+#[derive(Component, Default, Reflect)]
+#[reflect(Component)]
+pub struct GameCamera {
+    // Where the camera is looking, before shake
+    pub focus: Vec2,
+    pub trauma: f32,
+}
+
+fn spawn_camera(mut commands: Commands, settings: Res<CameraSettings>) {
+    let mut camera = Camera2dBundle::default();
+
+    camera.projection.scaling_mode = ScalingMode::AutoMin {
+        min_width: settings.min_width,
+        min_height: settings.min_height,
+    };
+
+    commands.spawn((camera, GameCamera::default()));
+}
+
+fn update_projection(
+    settings: Res<CameraSettings>,
+    mut projections: Query<&mut OrthographicProjection, With<GameCamera>>,
+) {
+    for mut projection in &mut projections {
+        projection.scaling_mode = ScalingMode::AutoMin {
+            min_width: settings.min_width,
+            min_height: settings.min_height,
+        };
+    }
+}
+
+fn follow_player(
+    settings: Res<CameraSettings>,
+    time: Res<Time>,
+    players: Query<&Transform, With<Player>>,
+    rooms: Query<(&Transform, &RoomTag, &RoomVisibility), Without<Player>>,
+    mut cameras: Query<(&mut GameCamera, &OrthographicProjection)>,
+) {
+    let Ok(player) = players.get_single() else {
+        return;
+    };
+    let player = player.translation.xy();
+
+    for (mut camera, projection) in &mut cameras {
+        // Only move far enough to put the player back on the edge of the dead zone
+        let offset = player - camera.focus;
+        let mut target =
+            camera.focus + offset - offset.clamp(-settings.dead_zone, settings.dead_zone);
+
+        let current_room = rooms
+            .iter()
+            .find(|(_, _, visibility)| **visibility == RoomVisibility::Current);
+        if let (true, Some((room_transform, room, _))) = (settings.clamp_to_room, current_room) {
+            // Grown by a tile so the room's own walls stay in view
+            let bounds = room.rect(room_transform.translation).inset(TILE_SIZE);
+            let half_view = projection.area.half_size();
+            for axis in 0..2 {
+                target[axis] = if bounds.half_size()[axis] <= half_view[axis] {
+                    bounds.center()[axis]
+                } else {
+                    target[axis].clamp(
+                        bounds.min[axis] + half_view[axis],
+                        bounds.max[axis] - half_view[axis],
+                    )
+                };
+            }
+        }
+
+        // Framerate independent easing towards the target
+        let t = 1. - (-settings.smoothing * time.delta_seconds()).exp();
+        camera.focus = camera.focus.lerp(target, t);
+    }
+}
+
+fn shake_camera(
+    time: Res<Time>,
+    mut shakes: EventReader<ShakeCamera>,
+    mut cameras: Query<(&mut Transform, &mut GameCamera)>,
+) {
+    let added: f32 = shakes.read().map(|shake| shake.0).sum();
+    for (mut transform, mut camera) in &mut cameras {
+        camera.trauma = (camera.trauma + added - TRAUMA_DECAY * time.delta_seconds()).clamp(0., 1.);
+
+        let seconds = time.elapsed_seconds();
+        let shake = Vec2::new((seconds * 53.).sin(), (seconds * 71.).cos())
+            * MAX_SHAKE_OFFSET
+            * camera.trauma.powi(2);
+        // Leaves z alone, so the camera keeps looking down on everything
+        transform.translation.x = camera.focus.x + shake.x;
+        transform.translation.y = camera.focus.y + shake.y;
+    }
+}
@@ -0,0 +1,167 @@
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+
+use crate::tile_grid::{TileGrid, TileKind};
+use crate::tilemap::TILE_SIZE;
+
+// Side of a spatial hash cell. Around the size of the biggest enemies, so most colliders only
+// land in one or two cells
+const HASH_CELL_SIZE: f32 = 32.;
+
+pub struct CollisionPlugin;
+
+impl Plugin for CollisionPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SpatialHash>()
+            .add_systems(PreUpdate, update_spatial_hash)
+            .register_type::<Collider>();
+    }
+}
+
+// Shape of an entity for collisions, centred on its translation and in world units, so it
+// ignores scale. Walls aren't entities, see `Collider::hits_grid`
+#[derive(Component, Clone, Copy, Debug, PartialEq, Reflect)]
+#[reflect(Component)]
+pub enum Collider {
+    Aabb { half_size: Vec2 },
+    Circle { radius: f32 },
+}
+
+impl Default for Collider {
+    fn default() -> Self {
+        Collider::aabb(Vec2::splat(TILE_SIZE))
+    }
+}
+
+impl Collider {
+    pub fn aabb(size: Vec2) -> Self {
+        Collider::Aabb {
+            half_size: size / 2.,
+        }
+    }
+
+    pub fn circle(radius: f32) -> Self {
+        Collider::Circle { radius }
+    }
+
+    // Half the size of the box around the shape
+    pub fn half_extents(&self) -> Vec2 {
+        match *self {
+            Collider::Aabb { half_size } => half_size,
+            Collider::Circle { radius } => Vec2::splat(radius),
+        }
+    }
+
+    pub fn overlaps(&self, position: Vec2, other: &Collider, other_position: Vec2) -> bool {
+        let offset = other_position - position;
+        match (*self, *other) {
+            (Collider::Aabb { half_size: a }, Collider::Aabb { half_size: b }) => {
+                offset.x.abs() < a.x + b.x && offset.y.abs() < a.y + b.y
+            }
+            (Collider::Circle { radius: a }, Collider::Circle { radius: b }) => {
+                offset.length_squared() < (a + b) * (a + b)
+            }
+            (Collider::Aabb { half_size }, Collider::Circle { radius }) => {
+                circle_overlaps_box(offset, radius, half_size)
+            }
+            (Collider::Circle { radius }, Collider::Aabb { half_size }) => {
+                circle_overlaps_box(-offset, radius, half_size)
+            }
+        }
+    }
+
+    // Whether the shape at `position` overlaps any solid tile
+    pub fn hits_grid(&self, position: Vec2, grid: &TileGrid) -> bool {
+        self.hits_tiles(position, grid, TileKind::is_solid)
+    }
+
+    // Whether the shape at `position` overlaps any tile that `blocks` says is in the way
+    pub fn hits_tiles(
+        &self,
+        position: Vec2,
+        grid: &TileGrid,
+        blocks: impl Fn(TileKind) -> bool,
+    ) -> bool {
+        let half = self.half_extents();
+        let min = grid.world_to_grid(position - half);
+        let max = grid.world_to_grid(position + half);
+        let tile = Vec2::splat(TILE_SIZE / 2.);
+        (min.y..=max.y).any(|y| {
+            (min.x..=max.x).any(|x| {
+                let cell = IVec2::new(x, y);
+                blocks(grid.get(cell))
+                    && self.overlaps(
+                        position,
+                        &Collider::Aabb { half_size: tile },
+                        grid.grid_to_world(cell),
+                    )
+            })
+        })
+    }
+}
+
+// `offset` is from the middle of the box to the middle of the circle
+fn circle_overlaps_box(offset: Vec2, radius: f32, half_size: Vec2) -> bool {
+    let closest = offset.clamp(-half_size, half_size);
+    offset.distance_squared(closest) < radius * radius
+}
+
+// Every collider, bucketed by the grid cells it covers, so finding what's touching something
+// only looks at its neighbours. Rebuilt at the start of each frame
+#[derive(Resource, Default)]
+pub struct SpatialHash {
+    cells: HashMap<IVec2, Vec<(Entity, Vec2, Collider)>>,
+}
+
+impl SpatialHash {
+    fn cells_covering(position: Vec2, collider: &Collider) -> impl Iterator<Item = IVec2> {
+        let half = collider.half_extents();
+        let min = ((position - half) / HASH_CELL_SIZE).floor().as_ivec2();
+        let max = ((position + half) / HASH_CELL_SIZE).floor().as_ivec2();
+        (min.y..=max.y).flat_map(move |y| (min.x..=max.x).map(move |x| IVec2::new(x, y)))
+    }
+
+    pub fn insert(&mut self, entity: Entity, position: Vec2, collider: Collider) {
+        for cell in Self::cells_covering(position, &collider) {
+            self.cells
+                .entry(cell)
+                .or_default()
+                .push((entity, position, collider));
+        }
+    }
+
+    // Every entity whose collider overlaps `collider` at `position`
+    pub fn overlapping(&self, position: Vec2, collider: &Collider) -> Vec<Entity> {
+        let mut found: Vec<Entity> = Vec::new();
+        for cell in Self::cells_covering(position, collider) {
+            let Some(entries) = self.cells.get(&cell) else {
+                continue;
+            };
+            for &(entity, other_position, other) in entries {
+                if !found.contains(&entity) && collider.overlaps(position, &other, other_position) {
+                    found.push(entity);
+                }
+            }
+        }
+        found
+    }
+
+    // Empties every cell, dropping the ones nothing was in last frame
+    fn clear(&mut self) {
+        self.cells.retain(|_, entries| {
+            let used = !entries.is_empty();
+            entries.clear();
+            used
+        });
+    }
+}
+
+fn update_spatial_hash(
+    mut hash: ResMut<SpatialHash>,
+    colliders: Query<(Entity, &GlobalTransform, &Collider)>,
+) {
+    hash.clear();
+    for (entity, transform, collider) in &colliders {
+        hash.insert(entity, transform.translation().xy(), *collider);
+    }
+}
@@ -0,0 +1,420 @@
+use std::fmt::Write as _;
+
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+
+use crate::cli_arg;
+use crate::main_menu::GameState;
+
+// Where bindings are read from and saved to, unless `--controls` says otherwise
+const DEFAULT_CONTROLS_PATH: &str = "controls.cfg";
+// How far a stick has to be pushed before it counts
+const STICK_DEAD_ZONE: f32 = 0.2;
+
+pub struct ControlsPlugin;
+
+impl Plugin for ControlsPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(InputMap::load())
+            .add_systems(Update, toggle_pause.run_if(in_state(GameState::Game)))
+            .add_systems(OnExit(GameState::Game), unpause);
+    }
+}
+
+// Everything the player can do with a key or gamepad button
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Action {
+    MoveUp,
+    MoveDown,
+    MoveLeft,
+    MoveRight,
+    ShootUp,
+    ShootDown,
+    ShootLeft,
+    ShootRight,
+    Shoot,
+    NextWeapon,
+    SpawnPig,
+    Pause,
+    ToggleMap,
+    ToggleInspector,
+}
+
+impl Action {
+    pub const ALL: [Action; 14] = [
+        Action::MoveUp,
+        Action::MoveDown,
+        Action::MoveLeft,
+        Action::MoveRight,
+        Action::ShootUp,
+        Action::ShootDown,
+        Action::ShootLeft,
+        Action::ShootRight,
+        Action::Shoot,
+        Action::NextWeapon,
+        Action::SpawnPig,
+        Action::Pause,
+        Action::ToggleMap,
+        Action::ToggleInspector,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Action::MoveUp => "Move Up",
+            Action::MoveDown => "Move Down",
+            Action::MoveLeft => "Move Left",
+            Action::MoveRight => "Move Right",
+            Action::ShootUp => "Shoot Up",
+            Action::ShootDown => "Shoot Down",
+            Action::ShootLeft => "Shoot Left",
+            Action::ShootRight => "Shoot Right",
+            Action::Shoot => "Shoot At Cursor",
+            Action::NextWeapon => "Next Weapon",
+            Action::SpawnPig => "Spawn Pig",
+            Action::Pause => "Pause",
+            Action::ToggleMap => "Map",
+            Action::ToggleInspector => "Inspector",
+        }
+    }
+
+    fn default_keys(self) -> Vec<KeyCode> {
+        match self {
+            Action::MoveUp => vec![KeyCode::W],
+            Action::MoveDown => vec![KeyCode::S],
+            Action::MoveLeft => vec![KeyCode::A],
+            Action::MoveRight => vec![KeyCode::D],
+            Action::ShootUp => vec![KeyCode::Up],
+            Action::ShootDown => vec![KeyCode::Down],
+            Action::ShootLeft => vec![KeyCode::Left],
+            Action::ShootRight => vec![KeyCode::Right],
+            Action::Shoot => vec![],
+            Action::NextWeapon => vec![KeyCode::Q],
+            Action::SpawnPig => vec![KeyCode::Space],
+            Action::Pause => vec![KeyCode::P],
+            Action::ToggleMap => vec![KeyCode::M],
+            Action::ToggleInspector => vec![KeyCode::Escape],
+        }
+    }
+
+    // Gamepad buttons for the action. Unlike keys these aren't rebindable
+    pub fn pad_buttons(self) -> &'static [GamepadButtonType] {
+        match self {
+            Action::NextWeapon => &[GamepadButtonType::North],
+            Action::SpawnPig => &[GamepadButtonType::South, GamepadButtonType::RightTrigger],
+            Action::Pause => &[GamepadButtonType::Start],
+            Action::ToggleMap => &[GamepadButtonType::Select],
+            _ => &[],
+        }
+    }
+
+    // Mouse buttons for the action, also not rebindable
+    pub fn mouse_buttons(self) -> &'static [MouseButton] {
+        match self {
+            Action::Shoot => &[MouseButton::Left],
+            _ => &[],
+        }
+    }
+
+    // Name used in the config file
+    fn name(self) -> String {
+        format!("{:?}", self)
+    }
+}
+
+// Keys that can be bound to an action. Also how key names in the config file are read back
+const BINDABLE_KEYS: [KeyCode; 58] = [
+    KeyCode::A,
+    KeyCode::B,
+    KeyCode::C,
+    KeyCode::D,
+    KeyCode::E,
+    KeyCode::F,
+    KeyCode::G,
+    KeyCode::H,
+    KeyCode::I,
+    KeyCode::J,
+    KeyCode::K,
+    KeyCode::L,
+    KeyCode::M,
+    KeyCode::N,
+    KeyCode::O,
+    KeyCode::P,
+    KeyCode::Q,
+    KeyCode::R,
+    KeyCode::S,
+    KeyCode::T,
+    KeyCode::U,
+    KeyCode::V,
+    KeyCode::W,
+    KeyCode::X,
+    KeyCode::Y,
+    KeyCode::Z,
+    KeyCode::Key0,
+    KeyCode::Key1,
+    KeyCode::Key2,
+    KeyCode::Key3,
+    KeyCode::Key4,
+    KeyCode::Key5,
+    KeyCode::Key6,
+    KeyCode::Key7,
+    KeyCode::Key8,
+    KeyCode::Key9,
+    KeyCode::Up,
+    KeyCode::Down,
+    KeyCode::Left,
+    KeyCode::Right,
+    KeyCode::Space,
+    KeyCode::Return,
+    KeyCode::Tab,
+    KeyCode::Escape,
+    KeyCode::Back,
+    KeyCode::ShiftLeft,
+    KeyCode::ShiftRight,
+    KeyCode::ControlLeft,
+    KeyCode::ControlRight,
+    KeyCode::AltLeft,
+    KeyCode::AltRight,
+    KeyCode::Comma,
+    KeyCode::Period,
+    KeyCode::Slash,
+    KeyCode::Semicolon,
+    KeyCode::Apostrophe,
+    KeyCode::BracketLeft,
+    KeyCode::BracketRight,
+];
+
+pub fn is_bindable(key: KeyCode) -> bool {
+    BINDABLE_KEYS.contains(&key)
+}
+
+fn parse_key(name: &str) -> Option<KeyCode> {
+    BINDABLE_KEYS
+        .into_iter()
+        .find(|key| format!("{:?}", key) == name)
+}
+
+// The keys bound to each action. Read from a config file of `Action = Key, Key` lines, where any
+// action left out keeps its default keys
+#[derive(Resource, Clone, Debug, PartialEq)]
+pub struct InputMap {
+    bindings: HashMap<Action, Vec<KeyCode>>,
+}
+
+impl Default for InputMap {
+    fn default() -> Self {
+        InputMap {
+            bindings: Action::ALL
+                .into_iter()
+                .map(|action| (action, action.default_keys()))
+                .collect(),
+        }
+    }
+}
+
+impl InputMap {
+    pub fn keys(&self, action: Action) -> &[KeyCode] {
+        self.bindings.get(&action).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn pressed(&self, action: Action, input: &Input<KeyCode>) -> bool {
+        input.any_pressed(self.keys(action).iter().copied())
+    }
+
+    pub fn just_pressed(&self, action: Action, input: &Input<KeyCode>) -> bool {
+        input.any_just_pressed(self.keys(action).iter().copied())
+    }
+
+    // Binds `key` to `action` alone, taking it off any other action first
+    pub fn bind(&mut self, action: Action, key: KeyCode) {
+        for keys in self.bindings.values_mut() {
+            keys.retain(|&bound| bound != key);
+        }
+        self.bindings.insert(action, vec![key]);
+    }
+
+    // Keys bound to `action`, for showing in menus
+    pub fn describe(&self, action: Action) -> String {
+        let keys: Vec<String> = self
+            .keys(action)
+            .iter()
+            .map(|key| format!("{:?}", key))
+            .collect();
+        if keys.is_empty() {
+            return "-".to_string();
+        }
+        keys.join(", ")
+    }
+
+    pub fn parse(config: &str) -> Self {
+        let mut map = InputMap::default();
+        for line in config.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((name, keys)) = line.split_once('=') else {
+                warn!("Ignoring control binding without '=': {}", line);
+                continue;
+            };
+            let Some(action) = Action::ALL
+                .into_iter()
+                .find(|action| action.name() == name.trim())
+            else {
+                warn!("Ignoring binding for unknown action: {}", name.trim());
+                continue;
+            };
+            let keys = keys
+                .split(',')
+                .map(str::trim)
+                .filter(|key| !key.is_empty())
+                .filter_map(|key| {
+                    let parsed = parse_key(key);
+                    if parsed.is_none() {
+                        warn!("Ignoring unknown key {} for {}", key, action.name());
+                    }
+                    parsed
+                })
+                .collect();
+            map.bindings.insert(action, keys);
+        }
+        map
+    }
+
+    pub fn to_config(&self) -> String {
+        let mut config = String::new();
+        for action in Action::ALL {
+            let keys: Vec<String> = self
+                .keys(action)
+                .iter()
+                .map(|key| format!("{:?}", key))
+                .collect();
+            let line = format!("{} = {}", action.name(), keys.join(", "));
+            let _ = writeln!(config, "{}", line.trim_end());
+        }
+        config
+    }
+
+    fn path() -> String {
+        cli_arg("controls").unwrap_or_else(|| DEFAULT_CONTROLS_PATH.to_string())
+    }
+
+    // Reads the config file, or falls back to the default bindings when there isn't one
+    pub fn load() -> Self {
+        match std::fs::read_to_string(Self::path()) {
+            Ok(config) => InputMap::parse(&config),
+            Err(_) => InputMap::default(),
+        }
+    }
+
+    pub fn save(&self) {
+        let path = Self::path();
+        if let Err(error) = std::fs::write(&path, self.to_config()) {
+            warn!("Couldn't save controls to {}: {}", path, error);
+        }
+    }
+}
+
+// Keyboard, mouse and gamepad input read through the input map. Gameplay systems read actions through
+// this rather than looking at keys or buttons themselves
+#[derive(SystemParam)]
+pub struct ActionInput<'w> {
+    map: Res<'w, InputMap>,
+    keys: Res<'w, Input<KeyCode>>,
+    mouse: Res<'w, Input<MouseButton>>,
+    buttons: Res<'w, Input<GamepadButton>>,
+    axes: Res<'w, Axis<GamepadAxis>>,
+    gamepads: Res<'w, Gamepads>,
+}
+
+impl ActionInput<'_> {
+    pub fn pressed(&self, action: Action) -> bool {
+        self.map.pressed(action, &self.keys)
+            || self.mouse.any_pressed(action.mouse_buttons().iter().copied())
+            || self.pad_buttons(action).any(|button| self.buttons.pressed(button))
+    }
+
+    pub fn just_pressed(&self, action: Action) -> bool {
+        self.map.just_pressed(action, &self.keys)
+            || self.mouse.any_just_pressed(action.mouse_buttons().iter().copied())
+            || self.pad_buttons(action).any(|button| self.buttons.just_pressed(button))
+    }
+
+    fn pad_buttons(&self, action: Action) -> impl Iterator<Item = GamepadButton> + '_ {
+        self.gamepads.iter().flat_map(move |gamepad| {
+            action
+                .pad_buttons()
+                .iter()
+                .map(move |&button| GamepadButton::new(gamepad, button))
+        })
+    }
+
+    // The first stick pushed past the dead zone on any gamepad, rescaled so it starts from 0 at
+    // the edge of the dead zone
+    fn stick(&self, x: GamepadAxisType, y: GamepadAxisType) -> Option<Vec2> {
+        self.gamepads.iter().find_map(|gamepad| {
+            let stick = Vec2::new(
+                self.axes.get(GamepadAxis::new(gamepad, x))?,
+                self.axes.get(GamepadAxis::new(gamepad, y))?,
+            );
+            let length = stick.length();
+            if length < STICK_DEAD_ZONE {
+                return None;
+            }
+            let scaled = ((length - STICK_DEAD_ZONE) / (1. - STICK_DEAD_ZONE)).min(1.);
+            Some(stick / length * scaled)
+        })
+    }
+
+    // How far to move on each axis this frame, from -1 to 1. The left stick moves slower the
+    // less it's pushed, and keys always move at full speed
+    pub fn movement(&self) -> Vec2 {
+        if let Some(stick) = self.stick(GamepadAxisType::LeftStickX, GamepadAxisType::LeftStickY) {
+            return stick;
+        }
+        let axis = |negative: Action, positive: Action| {
+            self.pressed(positive) as i32 as f32 - self.pressed(negative) as i32 as f32
+        };
+        Vec2::new(
+            axis(Action::MoveLeft, Action::MoveRight),
+            axis(Action::MoveDown, Action::MoveUp),
+        )
+    }
+
+    // Direction the right stick is pushed in, when it's pushed far enough to shoot
+    pub fn aim(&self) -> Option<Vec2> {
+        self.stick(GamepadAxisType::RightStickX, GamepadAxisType::RightStickY)
+            .and_then(Vec2::try_normalize)
+    }
+}
+
+// Run condition that flips between on and off each time `action` is pressed
+pub fn action_toggle_active(
+    default: bool,
+    action: Action,
+) -> impl FnMut(ActionInput) -> bool + Clone {
+    let mut active = default;
+    move |input: ActionInput| {
+        active ^= input.just_pressed(action);
+        active
+    }
+}
+
+// Run condition for systems that act on input, since pausing only stops time
+pub fn not_paused(time: Res<Time<Virtual>>) -> bool {
+    !time.is_paused()
+}
+
+fn toggle_pause(input: ActionInput, mut time: ResMut<Time<Virtual>>) {
+    if !input.just_pressed(Action::Pause) {
+        return;
+    }
+    if time.is_paused() {
+        time.unpause();
+    } else {
+        time.pause();
+    }
+}
+
+fn unpause(mut time: ResMut<Time<Virtual>>) {
+    time.unpause();
+}
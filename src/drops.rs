@@ -1,5 +1,11 @@
+use bevy::ecs::system::SystemParam;
 use bevy::prelude::*;
+use rand::seq::IteratorRandom;
+use rand::Rng;
 
+use crate::collision::{Collider, SpatialHash};
+use crate::projectile::ProjectileModifiers;
+use crate::seed::DungeonRng;
 use crate::{Money, Player};
 
 
@@ -10,7 +16,8 @@ impl Plugin for DropsPlugin {
     fn build(&self, app: &mut App) {
         app.add_systems(OnEnter(GameState::Game), spawn_drops_parent)
             .add_systems(Update, drops_lifetime.run_if(in_state(GameState::Game)))
-            .register_type::<Drops>();
+            .register_type::<Drops>()
+            .register_type::<ShopItem>();
     }
 }
 
@@ -20,9 +27,60 @@ pub struct Drops {
     pub drop_type: String,
 }
 
+// A drop that has to be paid for before it can be picked up
+#[derive(Component, Default, Reflect)]
+#[reflect(Component)]
+pub struct ShopItem {
+    pub price: f32,
+}
+
 #[derive(Component)]
 pub struct DropsParent;
 
+// How close the player has to get to pick a drop up
+const DROP_RADIUS: f32 = 6.;
+
+pub const DROP_TYPES: [&str; 3] = ["health", "coin", "damage_up"];
+
+// Pickups that add to the player's `ProjectileModifiers`, drawn as a tinted bullet. They're only
+// given out as rewards, since enemy drops would stack them up too fast
+const MODIFIER_DROPS: [(&str, Color, ProjectileModifiers); 3] = [
+    (
+        "pierce",
+        Color::CYAN,
+        ProjectileModifiers {
+            pierce: 1,
+            ..ProjectileModifiers::NONE
+        },
+    ),
+    (
+        "ricochet",
+        Color::ORANGE,
+        ProjectileModifiers {
+            ricochet: 1,
+            ..ProjectileModifiers::NONE
+        },
+    ),
+    (
+        "homing",
+        Color::VIOLET,
+        ProjectileModifiers {
+            homing: 2.,
+            ..ProjectileModifiers::NONE
+        },
+    ),
+];
+
+fn modifier_drop(drop: &str) -> Option<&(&'static str, Color, ProjectileModifiers)> {
+    MODIFIER_DROPS.iter().find(|(name, ..)| *name == drop)
+}
+
+// What clearing a room or finding a treasure room gives: any normal drop, or a modifier
+pub fn random_reward(rng: &mut impl Rng) -> &'static str {
+    let modifiers = MODIFIER_DROPS.iter().map(|(drop, ..)| *drop);
+    DROP_TYPES.into_iter().chain(modifiers).choose(rng).unwrap()
+}
+
 fn spawn_drops_parent(mut commands: Commands) {
     commands.spawn((
         SpatialBundle::default(),
@@ -31,29 +89,98 @@ fn spawn_drops_parent(mut commands: Commands) {
     ));
 }
 
+pub fn spawn_drop(
+    commands: &mut ChildBuilder,
+    asset_server: &AssetServer,
+    drop: &str,
+    transform: Transform,
+) -> Entity {
+    let (texture, color) = match modifier_drop(drop) {
+        Some((_, color, _)) => ("bullet.png".to_string(), *color),
+        None => (format!("{}.png", drop), Color::WHITE),
+    };
+    let sprite = Sprite {
+        color,
+        custom_size: Some(Vec2::splat(7.)),
+        ..Default::default()
+    };
+
+    commands
+        .spawn((
+            SpriteBundle {
+                sprite,
+                texture: asset_server.load(texture),
+                transform,
+                ..default()
+            },
+            Drops {
+                drop_type: drop.to_string(),
+            },
+            Collider::circle(DROP_RADIUS),
+            Name::new(drop.to_string()),
+        ))
+        .id()
+}
+
+// How much each damage pickup adds to the player's damage multiplier
+const DAMAGE_UP: f32 = 0.25;
+
+// What systems need to leave drops lying around the level
+#[derive(SystemParam)]
+pub struct DropSpawning<'w, 's> {
+    parent: Query<'w, 's, Entity, With<DropsParent>>,
+    asset_server: Res<'w, AssetServer>,
+    pub rng: ResMut<'w, DungeonRng>,
+}
+
+impl DropSpawning<'_, '_> {
+    pub fn spawn(&self, commands: &mut Commands, drop: &str, transform: Transform) {
+        commands.entity(self.parent.single()).with_children(|commands| {
+            spawn_drop(commands, &self.asset_server, drop, transform);
+        });
+    }
+
+    // See `random_reward`, picked with the loot rng
+    pub fn spawn_reward(&mut self, commands: &mut Commands, transform: Transform) {
+        let drop = random_reward(&mut self.rng.loot);
+        self.spawn(commands, drop, transform);
+    }
+}
+
 fn drops_lifetime(
     mut commands: Commands,
-    mut drops: Query<(Entity, &mut Transform, &mut Drops), Without<Player>>,
-    mut player_transform: Query<(&mut Transform, &mut Player), With<Player>>,
+    drops: Query<(&Drops, Option<&ShopItem>)>,
+    mut player_transform: Query<(&Transform, &mut Player, &mut ProjectileModifiers, &Collider)>,
     parent: Query<Entity, With<DropsParent>>,
     mut money: ResMut<Money>,
+    hash: Res<SpatialHash>,
 ) {
     let parent = parent.single();
-    let (player_transform,  mut player) = player_transform.single_mut();
-
-    for (drop_entity, drop_transform, drop) in &mut drops {
-        let distance = drop_transform
-            .translation
-            .distance(player_transform.translation);
-        if distance < 10. {
-            if drop.drop_type== "COIN"{
+    let (player_transform,  mut player, mut modifiers, collider) = player_transform.single_mut();
+
+    for drop_entity in hash.overlapping(player_transform.translation.xy(), collider) {
+        if let Ok((drop, shop_item)) = drops.get(drop_entity) {
+            if let Some(shop_item) = shop_item {
+                if money.0 < shop_item.price {
+                    continue;
+                }
+                money.0 -= shop_item.price;
+                info!("Bought {} for ${:?}, remaining money: ${:?}", drop.drop_type, shop_item.price, money.0);
+            }
+            if drop.drop_type== "coin"{
                 money.0 += 10.;
             }
             else if drop.drop_type=="health" {
                 player.health += 10.;
             }
+            else if drop.drop_type=="damage_up" {
+                player.damage_multiplier += DAMAGE_UP;
+            }
+            else if let Some((_, _, pickup)) = modifier_drop(&drop.drop_type) {
+                *modifiers = *modifiers + *pickup;
+            }
             commands.entity(parent).remove_children(&[drop_entity]);
-            commands.entity(drop_entity).despawn();
+            commands.entity(drop_entity).despawn_recursive();
 
         }
 
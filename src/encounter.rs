@@ -0,0 +1,127 @@
+use bevy::prelude::*;
+
+use crate::drops::DropSpawning;
+use crate::enemy::Enemy;
+use crate::enemy_spawner::{EnemySpawner, EnemySpawning};
+use crate::layout::RoomKind;
+use crate::main_menu::GameState;
+use crate::map_gen::RoomTag;
+use crate::player::Player;
+use crate::tile_grid::TileGrid;
+use crate::tilemap::TILE_SIZE;
+
+// One enemy per this much floor area, within `MIN_WAVE..=MAX_WAVE` on the first floor. Deeper
+// floors scale both the wave and its cap
+const WAVE_AREA: f32 = 150. * 150.;
+const MIN_WAVE: u32 = 2;
+const MAX_WAVE: u32 = 8;
+// How far past the doorway the player has to walk before the doors shut behind them
+const LOCK_MARGIN: f32 = TILE_SIZE * 2.;
+
+pub struct EncounterPlugin;
+
+impl Plugin for EncounterPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Encounter>().add_systems(
+            Update,
+            // Finishing runs first so it never sees an encounter whose spawner hasn't been
+            // spawned yet
+            (finish_encounter, start_encounter)
+                .chain()
+                .run_if(in_state(GameState::Game)),
+        );
+    }
+}
+
+// The room the player is currently locked in, if any
+#[derive(Resource, Default)]
+pub struct Encounter {
+    pub room: Option<Entity>,
+}
+
+fn start_encounter(
+    mut commands: Commands,
+    mut encounter: ResMut<Encounter>,
+    players: Query<&Transform, With<Player>>,
+    rooms: Query<(Entity, &Transform, &RoomTag)>,
+    mut grid: ResMut<TileGrid>,
+    mut spawning: EnemySpawning,
+) {
+    if encounter.room.is_some() {
+        return;
+    }
+    let Ok(player) = players.get_single() else {
+        return;
+    };
+
+    for (room_entity, room_transform, room) in &rooms {
+        if room.cleared
+            || !room
+                .rect(room_transform.translation)
+                .inset(-LOCK_MARGIN)
+                .contains(player.translation.xy())
+        {
+            continue;
+        }
+
+        // Cave and tunnel sectors have no doors, so walking into one just sets off its wave, from
+        // the spawn points the generator scattered over its open floor
+        encounter.room = Some(room_entity);
+        grid.set_doors_locked(&room.doors, true);
+
+        let difficulty = spawning.difficulty();
+        let mut wave = ((room.width * room.height) / WAVE_AREA * difficulty) as u32;
+        if room.kind == RoomKind::Boss {
+            // The boss brings a smaller escort than a normal wave
+            wave /= 2;
+            let translation = room_transform.translation.xy().extend(0.);
+            spawning.spawn_boss(&mut commands, translation, room_entity);
+        }
+        commands.spawn((
+            EnemySpawner {
+                cooldown: 0.5,
+                timer: 0.5,
+                room: Some(room_entity),
+                remaining: wave.clamp(MIN_WAVE, (MAX_WAVE as f32 * difficulty) as u32),
+            },
+            Name::new("Enemy Spawner"),
+        ));
+        if room.doors.is_empty() {
+            info!("Wave started! Transform: {:?}", room_transform.translation);
+        } else {
+            info!("Doors locked! Transform: {:?}", room_transform.translation);
+        }
+        break;
+    }
+}
+
+fn finish_encounter(
+    mut commands: Commands,
+    mut encounter: ResMut<Encounter>,
+    mut rooms: Query<(&Transform, &mut RoomTag)>,
+    spawners: Query<&EnemySpawner>,
+    enemies: Query<&Enemy>,
+    mut grid: ResMut<TileGrid>,
+    mut drops: DropSpawning,
+) {
+    let Some(room_entity) = encounter.room else {
+        return;
+    };
+    if spawners.iter().any(|spawner| spawner.room == Some(room_entity))
+        || enemies.iter().any(|enemy| enemy.room == Some(room_entity))
+    {
+        return;
+    }
+
+    encounter.room = None;
+    let Ok((room_transform, mut room)) = rooms.get_mut(room_entity) else {
+        return;
+    };
+    room.cleared = true;
+    grid.set_doors_locked(&room.doors, false);
+
+    let mut transform = *room_transform;
+    transform.translation.z = -1.0;
+    drops.spawn_reward(&mut commands, transform);
+    info!("Room cleared! Transform: {:?}", room_transform.translation);
+}
@@ -1,24 +1,41 @@
 use rand::seq::SliceRandom;
 use rand::Rng;
 
-use bevy::{prelude::*, sprite::collide_aabb::collide};
+use bevy::prelude::*;
 
 use crate::main_menu::GameState;
 use crate::{
-    drops::{Drops, DropsParent},
+    drops::{DropSpawning, DROP_TYPES},
     Player,
 };
 
+use std::f32::consts::TAU;
+
+use crate::camera::ShakeCamera;
+use crate::collision::{Collider, SpatialHash};
+use crate::controls::not_paused;
 use crate::player::wall_collision_check;
-use crate::tilemap::TileCollider;
+use crate::projectile::{spawn_projectile, Faction, Projectile, ProjectileParent};
+use crate::tile_grid::Pathfinding;
+
+// Camera shake per point of damage the player takes
+pub const HIT_SHAKE: f32 = 0.02;
+// How far enemy shots fly
+const ENEMY_SHOT_RANGE: f32 = 240.;
 
 pub struct EnemyPlugin;
 
 impl Plugin for EnemyPlugin {
     fn build(&self, app: &mut App) {
         app.add_systems(OnEnter(GameState::Game), spawn_enemy_parent)
-            .add_systems(Update, enemy_lifetime.run_if(in_state(GameState::Game)))
-            .register_type::<Enemy>();
+            .add_systems(
+                Update,
+                // Contact damage isn't scaled by time, so it has to be stopped outright while paused
+                (enemy_death, enemy_lifetime, enemy_shooting)
+                    .run_if(in_state(GameState::Game).and_then(not_paused)),
+            )
+            .register_type::<Enemy>()
+            .register_type::<EnemyGun>();
     }
 }
 
@@ -28,7 +45,63 @@ pub struct Enemy {
     pub health: f32,
     pub speed: f32,
     pub collision_damage: f32,
-    pub radius: f32,
+    // The room whose encounter spawned this enemy
+    pub room: Option<Entity>,
+}
+
+// How an enemy's shots are laid out
+#[derive(Clone, Copy, Debug, Default, PartialEq, Reflect)]
+pub enum FirePattern {
+    // One shot straight at the player
+    #[default]
+    Aimed,
+    // `count` shots evenly all the way round
+    Radial { count: u32 },
+    // `arms` shots evenly round, turning `turn` radians further each time
+    Spiral { arms: u32, turn: f32 },
+}
+
+// Lets an enemy shoot at the player, once every time `timer` goes off
+#[derive(Component, Default, Reflect)]
+#[reflect(Component)]
+pub struct EnemyGun {
+    pub pattern: FirePattern,
+    pub timer: Timer,
+    pub speed: f32,
+    pub damage: f32,
+    pub range: f32,
+    // Where the spiral has got to
+    pub angle: f32,
+}
+
+impl EnemyGun {
+    pub fn new(pattern: FirePattern, interval: f32, speed: f32, damage: f32) -> Self {
+        EnemyGun {
+            pattern,
+            timer: Timer::from_seconds(interval, TimerMode::Repeating),
+            speed,
+            damage,
+            range: ENEMY_SHOT_RANGE,
+            angle: 0.,
+        }
+    }
+
+    // Directions to fire in for one shot, given the way to the player
+    fn shot_directions(&mut self, to_player: Vec2) -> Vec<Vec2> {
+        let ring = |count: u32, start: Vec2| -> Vec<Vec2> {
+            (0..count)
+                .map(|i| Vec2::from_angle(TAU * i as f32 / count as f32).rotate(start))
+                .collect()
+        };
+        match self.pattern {
+            FirePattern::Aimed => vec![to_player],
+            FirePattern::Radial { count } => ring(count, to_player),
+            FirePattern::Spiral { arms, turn } => {
+                self.angle = (self.angle + turn) % TAU;
+                ring(arms, Vec2::from_angle(self.angle))
+            }
+        }
+    }
 }
 
 #[derive(Component)]
@@ -43,128 +116,134 @@ fn spawn_enemy_parent(mut commands: Commands) {
 }
 
 
-fn enemy_lifetime(
+fn enemy_death(
     mut commands: Commands,
-    time: Res<Time>,
-    mut enemies: Query<
-        (Entity, &mut Transform, &mut Enemy),
-        (Without<Player>, Without<TileCollider>),
-    >,
-    mut player_query: Query<(&mut Transform, &mut Player), (With<Player>, Without<TileCollider>)>,
+    enemies: Query<(Entity, &Transform, &Enemy)>,
     parent: Query<Entity, With<EnemyParent>>,
-    drops_parent: Query<Entity, With<DropsParent>>,
-    asset_server: Res<AssetServer>,
-    wall_query: Query<&Transform, (With<TileCollider>, Without<Player>)>,
+    mut drops: DropSpawning,
 ) {
     let parent = parent.single();
-    let drops_parent = drops_parent.single();
-    let (mut player_transform, mut player) = player_query.single_mut();
-    let mut rng = rand::thread_rng();
 
     for (enemy_entity, enemy_transform, enemy) in enemies.iter() {
         if enemy.health <= 0.0 {
             let transform = &mut enemy_transform.clone();
             transform.translation.z = -1.0;
             transform.scale = Vec3::splat(1.);
+            let rng = &mut drops.rng.loot;
             if rng.gen_bool(1.) {
-                let drop_arr = ["health", "coin", "damage_up"];
-                let drop = drop_arr.choose(&mut rand::thread_rng()).unwrap();
-                let sprite = Sprite {
-                    custom_size: Some(Vec2::splat(7.)),
-                    ..Default::default()
-                };
-
-                commands.entity(drops_parent).with_children(|commands| {
-                    commands.spawn((
-                        SpriteBundle {
-                            sprite,
-                            texture: asset_server.load(format!("{}.png", drop)),
-                            transform: *transform,
-
-                            ..default()
-                        },
-                        Drops {
-                            drop_type: format!("{}", drop),
-                        },
-                        Name::new(format!("{}", drop)),
-                    ));
-                });
+                let drop = DROP_TYPES.choose(rng).unwrap();
+                drops.spawn(&mut commands, drop, *transform);
             }
             commands.entity(parent).remove_children(&[enemy_entity]);
             commands.entity(enemy_entity).despawn();
         }
     }
+}
 
-    let mut iter = enemies.iter_combinations_mut::<2>();
-
-    while let Some([(_, mut enemy_transform, enemy), (_, enemy_transform2, enemy2)]) =
-        iter.fetch_next()
-    {
-        /*
-        println!("{}",enemy.radius);
-        println!("{}",enemy2.radius); */
-        let mut movement_amount = enemy.speed/10.
-            * Vec3::normalize(player_transform.translation - enemy_transform.translation)
-            * time.delta_seconds();
-
-        if player_collision(
-            player_transform.translation,
-            enemy_transform.translation + movement_amount,
-            enemy.radius,
+fn enemy_lifetime(
+    time: Res<Time>,
+    mut enemies: Query<(Entity, &mut Transform, &Enemy, &Collider), Without<Player>>,
+    others: Query<(), With<Enemy>>,
+    mut player_query: Query<(&mut Transform, &mut Player, &Collider), Without<Enemy>>,
+    pathfinding: Pathfinding,
+    mut shake: EventWriter<ShakeCamera>,
+    hash: Res<SpatialHash>,
+) {
+    let (mut player_transform, mut player, player_collider) = player_query.single_mut();
+
+    for (enemy_entity, mut enemy_transform, enemy, collider) in &mut enemies {
+        // Follow the flow field round walls, and head straight for the player when there's no
+        // path, e.g. once close enough to share a tile
+        let direction = pathfinding
+            .direction(enemy_transform.translation.xy())
+            .map(|direction| direction.extend(0.))
+            .unwrap_or_else(|| {
+                Vec3::normalize(player_transform.translation - enemy_transform.translation)
+            });
+        let movement_amount = enemy.speed/10. * direction * time.delta_seconds();
+        let grid = &pathfinding.grid;
+        // Slide along walls rather than stopping dead, the same as the player
+        let position = enemy_transform.translation;
+        let target = [
+            movement_amount,
+            Vec3::new(movement_amount.x, 0., 0.),
+            Vec3::new(0., movement_amount.y, 0.),
+        ]
+        .into_iter()
+        .map(|movement| position + movement)
+        .find(|&target| wall_collision_check(target, collider, grid))
+        .unwrap_or(position);
+
+        if collider.overlaps(
+            target.xy(),
+            player_collider,
+            player_transform.translation.xy(),
         ) {
-            movement_amount = movement_amount / enemy.speed * 5.;
-            let movement_x = Vec3::new(movement_amount.x, 0., 0.);
-            let movement_y = Vec3::new(0., movement_amount.y, 0.);
+            let push = movement_amount / enemy.speed * 5.;
+            let movement_x = Vec3::new(push.x, 0., 0.);
+            let movement_y = Vec3::new(0., push.y, 0.);
 
-            if wall_collision_check(movement_x + player_transform.translation, &wall_query) {
+            if wall_collision_check(movement_x + player_transform.translation, player_collider, grid) {
                 player_transform.translation += movement_x;
             }
-            if wall_collision_check(movement_y + player_transform.translation, &wall_query) {
+            if wall_collision_check(movement_y + player_transform.translation, player_collider, grid) {
                 player_transform.translation += movement_y;
             }
 
-            //enemy.health -= 1.0;
             player.health -= enemy.collision_damage;
-        } else if !enemy_collision(
-            enemy_transform.translation + movement_amount,
-            enemy.radius,
-            enemy_transform2.translation,
-            enemy2.radius,
-        ) && ! enemy_collision(
-            enemy_transform.translation,
-            enemy.radius,
-            enemy_transform2.translation,
-            enemy2.radius,
-        ){
-            enemy_transform.translation += movement_amount
+            shake.send(ShakeCamera(enemy.collision_damage * HIT_SHAKE));
+            continue;
         }
-        else{
-            //println!("collided")
+
+        // Enemies already overlapping are allowed to move apart, but not to walk into another
+        let touching = hash.overlapping(enemy_transform.translation.xy(), collider);
+        let blocked = hash
+            .overlapping(target.xy(), collider)
+            .into_iter()
+            .any(|other| other != enemy_entity && !touching.contains(&other) && others.contains(other));
+        if !blocked {
+            enemy_transform.translation = target;
         }
     }
 }
 
-fn player_collision(target_player: Vec3, target_enemy: Vec3, enemy_radius: f32) -> bool {
-    let collision = collide(
-        target_player,
-        Vec2::splat(enemy_radius),
-        target_enemy,
-        Vec2::splat(5.),
-    );
-    return collision.is_some();
-}
+fn enemy_shooting(
+    mut commands: Commands,
+    time: Res<Time>,
+    asset_server: Res<AssetServer>,
+    mut guns: Query<(&GlobalTransform, &mut EnemyGun, &Enemy)>,
+    players: Query<&Transform, With<Player>>,
+    parent: Query<Entity, With<ProjectileParent>>,
+) {
+    let Ok(player_transform) = players.get_single() else {
+        return;
+    };
+    let parent = parent.single();
+    let texture: Handle<Image> = asset_server.load("bullet.png");
 
-fn enemy_collision(
-    target_enemy: Vec3,
-    target_radius: f32,
-    enemy2_pos: Vec3,
-    enemy2_radius: f32,
-) -> bool {
-    let collision = collide(
-        target_enemy,
-        Vec2::splat(target_radius),
-        enemy2_pos,
-        Vec2::splat(enemy2_radius),
-    );
-    return collision.is_some();
+    for (transform, mut gun, enemy) in &mut guns {
+        // Killed this frame, and on its way out
+        if enemy.health <= 0. {
+            continue;
+        }
+        if !gun.timer.tick(time.delta()).just_finished() {
+            continue;
+        }
+        let position = transform.translation();
+        let to_player = (player_transform.translation.xy() - position.xy())
+            .try_normalize()
+            .unwrap_or(Vec2::X);
+        let (speed, damage, range) = (gun.speed, gun.damage, gun.range);
+        let directions = gun.shot_directions(to_player);
+        commands.entity(parent).with_children(|commands| {
+            for direction in directions {
+                spawn_projectile(
+                    commands,
+                    texture.clone(),
+                    position,
+                    Projectile::new(direction, speed, damage, range, Faction::Enemy),
+                );
+            }
+        });
+    }
 }
@@ -1,12 +1,21 @@
 
 
+use bevy::ecs::system::SystemParam;
 use bevy::{prelude::*, sprite::MaterialMesh2dBundle, window::PrimaryWindow};
+use rand::seq::SliceRandom;
 use rand::Rng;
 
-use crate::enemy::{Enemy,EnemyParent};
+use crate::collision::Collider;
+use crate::enemy::{Enemy, EnemyGun, EnemyParent, FirePattern};
+use crate::floor::DungeonFloor;
 use crate::main_menu::GameState;
+use crate::map_file::MapTile;
+use crate::map_gen::RoomTag;
+use crate::seed::DungeonRng;
 
 
+const SPAWN_MARGIN: f32 = 24.;
+
 pub struct EnemySpawnerPlugin;
 
 impl Plugin for EnemySpawnerPlugin {
@@ -21,20 +30,60 @@ impl Plugin for EnemySpawnerPlugin {
 pub struct EnemySpawner {
     pub cooldown: f32,
     pub timer: f32,
+    // Spawners tied to a room spawn inside it and go away after `remaining` enemies, otherwise
+    // they spawn across the window forever
+    pub room: Option<Entity>,
+    pub remaining: u32,
+}
+
+// What systems need to add enemies to the level
+#[derive(SystemParam)]
+pub struct EnemySpawning<'w, 's> {
+    parent: Query<'w, 's, Entity, With<EnemyParent>>,
+    meshes: ResMut<'w, Assets<Mesh>>,
+    materials: ResMut<'w, Assets<ColorMaterial>>,
+    floor: Res<'w, DungeonFloor>,
+}
+
+impl EnemySpawning<'_, '_> {
+    // See `DungeonFloor::difficulty`
+    pub fn difficulty(&self) -> f32 {
+        self.floor.difficulty()
+    }
+
+    pub fn spawn_enemy(
+        &mut self,
+        commands: &mut Commands,
+        rng: &mut impl Rng,
+        translation: Vec3,
+        room: Option<Entity>,
+    ) {
+        let difficulty = self.difficulty();
+        let (meshes, materials) = (&mut self.meshes, &mut self.materials);
+        commands.entity(self.parent.single()).with_children(|commands| {
+            spawn_enemy(commands, meshes, materials, rng, translation, room, difficulty);
+        });
+    }
+
+    pub fn spawn_boss(&mut self, commands: &mut Commands, translation: Vec3, room: Entity) {
+        let difficulty = self.difficulty();
+        let (meshes, materials) = (&mut self.meshes, &mut self.materials);
+        commands.entity(self.parent.single()).with_children(|commands| {
+            spawn_boss(commands, meshes, materials, translation, room, difficulty);
+        });
+    }
 }
 
 pub fn update_spawning(
     primary_query: Query<&Window, With<PrimaryWindow>>,
-    mut spawner_query: Query<&mut EnemySpawner>,
+    mut spawner_query: Query<(Entity, &mut EnemySpawner)>,
+    rooms: Query<(&Transform, &RoomTag)>,
     time: Res<Time>,
     mut commands: Commands,
-    parent: Query<Entity, With<EnemyParent>>,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut dungeon_rng: ResMut<DungeonRng>,
+    mut spawning: EnemySpawning,
 ) {
-    let parent = parent.single();
-
-    for mut spawner in spawner_query.iter_mut() {
+    for (spawner_entity, mut spawner) in spawner_query.iter_mut() {
         spawner.timer -= time.delta_seconds();
         if spawner.timer <= 0. {
             let Ok(primary) = primary_query.get_single() else {
@@ -42,44 +91,113 @@ pub fn update_spawning(
             };
             spawner.timer = spawner.cooldown;
 
-            let mut spawn_transform = Transform::from_scale(Vec3::splat(5.));
+            let rng = &mut dungeon_rng.spawns;
+            let translation = if let Some((room_transform, room)) =
+                spawner.room.and_then(|room| rooms.get(room).ok())
+            {
+                let spawn_points: Vec<Vec2> = room
+                    .features
+                    .iter()
+                    .filter(|(_, feature)| *feature == MapTile::EnemySpawn)
+                    .map(|(position, _)| *position)
+                    .collect();
+                if let Some(spawn_point) = spawn_points.choose(rng) {
+                    spawn_point.extend(0.)
+                } else {
+                // Keep clear of the walls so enemies don't spawn stuck in them
+                let half_size = Vec2::new(room.width, room.height) / 2. - SPAWN_MARGIN;
+                let offset = Vec2::new(
+                    rng.gen_range(-half_size.x..half_size.x),
+                    rng.gen_range(-half_size.y..half_size.y),
+                );
+                (room_transform.translation.xy() + offset).extend(0.)
+                }
+            } else {
+                Vec3::new(rng.gen_range(-primary.height()/2.0..primary.height()/2.),rng.gen_range(-primary.width()/2.0..primary.width()/2.0),0.)
+            };
+
+            spawning.spawn_enemy(&mut commands, rng, translation, spawner.room);
 
-            let mut rng = rand::thread_rng();
-/*             let texture;
-            if rng.gen_bool(0.5){
-                texture = asset_server.load("triangle.png");
+            if spawner.room.is_some() {
+                spawner.remaining = spawner.remaining.saturating_sub(1);
+                if spawner.remaining == 0 {
+                    commands.entity(spawner_entity).despawn();
+                }
             }
-            else{
-                texture = asset_server.load("square.png");
-                
-            } */
-            let side = rng.gen_range(3..8);
-            let mult = side as f32;
-
-            spawn_transform.translation = Vec3::new(rng.gen_range(-primary.height()/2.0..primary.height()/2.),rng.gen_range(-primary.width()/2.0..primary.width()/2.0),0.);
-            let radius = rng.gen_range(1.0..3.0);
-            spawn_transform.scale = Vec3::splat(radius);
-
-
-
-            
-            commands.entity(parent).with_children(|commands| {
-                commands.spawn((
-                    MaterialMesh2dBundle {
-                        mesh: meshes.add(shape::RegularPolygon::new(3.,side).into()).into(),
-                        material: materials.add(ColorMaterial::from(Color::rgb(rng.gen_range(0.0..1.0),rng.gen_range(0.0..1.0),rng.gen_range(0.0..1.0)))),
-                        transform: spawn_transform,
-                        ..default()
-                    },
-                    Enemy {
-                        health: 25.*mult,
-                        speed: 20.0,//+ 20.*(time.elapsed_seconds()/60.).floor(),
-                        collision_damage:1.,
-                        radius:radius*5.       
-                    },
-                    Name::new("Enemy"),
-                ));
-            });
         }
     }
 }
+
+fn spawn_enemy(
+    commands: &mut ChildBuilder,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<ColorMaterial>,
+    rng: &mut impl Rng,
+    translation: Vec3,
+    room: Option<Entity>,
+    // Scales health, see `DungeonFloor::difficulty`
+    difficulty: f32,
+) -> Entity {
+    let side = rng.gen_range(3..8);
+    let mult = side as f32;
+    let radius = rng.gen_range(1.0..3.0);
+    let spawn_transform = Transform::from_translation(translation).with_scale(Vec3::splat(radius));
+
+    let mut enemy = commands.spawn((
+        MaterialMesh2dBundle {
+            mesh: meshes.add(shape::RegularPolygon::new(3.,side).into()).into(),
+            material: materials.add(ColorMaterial::from(Color::rgb(rng.gen_range(0.0..1.0),rng.gen_range(0.0..1.0),rng.gen_range(0.0..1.0)))),
+            transform: spawn_transform,
+            ..default()
+        },
+        Enemy {
+            health: 25.*mult*difficulty,
+            speed: 20.0,//+ 20.*(time.elapsed_seconds()/60.).floor(),
+            collision_damage:1.,
+            room,
+        },
+        Collider::circle(radius * 2.5),
+        Name::new("Enemy"),
+    ));
+    // Triangles and pentagons shoot back. Going by shape keeps the seeded rolls the same
+    match side {
+        3 => {
+            enemy.insert(EnemyGun::new(FirePattern::Aimed, 2., 90., 5.));
+        }
+        5 => {
+            enemy.insert(EnemyGun::new(FirePattern::Radial { count: 5 }, 3., 70., 4.));
+        }
+        _ => {}
+    }
+    enemy.id()
+}
+
+fn spawn_boss(
+    commands: &mut ChildBuilder,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<ColorMaterial>,
+    translation: Vec3,
+    room: Entity,
+    difficulty: f32,
+) -> Entity {
+    let radius = 6.;
+    commands
+        .spawn((
+            MaterialMesh2dBundle {
+                mesh: meshes.add(shape::RegularPolygon::new(3., 8).into()).into(),
+                material: materials.add(ColorMaterial::from(Color::MAROON)),
+                transform: Transform::from_translation(translation).with_scale(Vec3::splat(radius)),
+                ..default()
+            },
+            Enemy {
+                health: 1000. * difficulty,
+                speed: 15.,
+                collision_damage: 3.,
+                room: Some(room),
+            },
+            Collider::circle(radius * 2.5),
+            EnemyGun::new(FirePattern::Spiral { arms: 3, turn: 0.3 }, 0.2, 60., 3.),
+            Name::new("Boss"),
+        ))
+        .id()
+}
@@ -0,0 +1,160 @@
+use bevy::prelude::*;
+
+use crate::drops::DropsParent;
+use crate::encounter::Encounter;
+use crate::enemy::EnemyParent;
+use crate::enemy_spawner::EnemySpawner;
+use crate::layout::RoomKind;
+use crate::main_menu::GameState;
+use crate::map_gen::{CorridorTag, RoomTag};
+use crate::player::Player;
+use crate::projectile::ProjectileParent;
+use crate::tilemap::TILE_SIZE;
+use crate::wall::WallParent;
+
+// How much tougher each floor gets than the one above it
+const DIFFICULTY_PER_FLOOR: f32 = 0.25;
+// How close the player has to get to the stairs to take them
+const STAIRS_RADIUS: f32 = TILE_SIZE * 1.5;
+const STAIRS_SIZE: f32 = 16.;
+// Keeps the stairs clear of the reward dropped in the middle of the boss room
+const STAIRS_OFFSET: Vec2 = Vec2::new(0., 24.);
+
+pub struct FloorPlugin;
+
+impl Plugin for FloorPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<DungeonFloor>()
+            .add_event::<NextFloor>()
+            .add_systems(OnEnter(GameState::Game), reset_floor)
+            .add_systems(
+                Update,
+                (spawn_stairs, take_stairs, change_floor)
+                    .chain()
+                    .run_if(in_state(GameState::Game)),
+            )
+            .register_type::<DungeonFloor>()
+            .register_type::<Stairs>();
+    }
+}
+
+// How deep the player is, starting from floor 1. Changing it generates a new level
+#[derive(Resource, Reflect)]
+#[reflect(Resource)]
+pub struct DungeonFloor(pub u32);
+
+impl Default for DungeonFloor {
+    fn default() -> Self {
+        DungeonFloor(1)
+    }
+}
+
+impl DungeonFloor {
+    // Multiplier for enemy health and wave sizes
+    pub fn difficulty(&self) -> f32 {
+        1. + DIFFICULTY_PER_FLOOR * self.0.saturating_sub(1) as f32
+    }
+}
+
+#[derive(Component, Default, Reflect)]
+#[reflect(Component)]
+pub struct Stairs;
+
+// Sent when the player takes the stairs down
+#[derive(Event)]
+pub struct NextFloor;
+
+fn reset_floor(mut floor: ResMut<DungeonFloor>) {
+    floor.0 = 1;
+}
+
+fn spawn_stairs(
+    mut commands: Commands,
+    rooms: Query<(&Transform, &RoomTag), Changed<RoomTag>>,
+    stairs: Query<(), With<Stairs>>,
+    asset_server: Res<AssetServer>,
+) {
+    if !stairs.is_empty() {
+        return;
+    }
+
+    for (room_transform, room) in &rooms {
+        if room.kind != RoomKind::Boss || !room.cleared {
+            continue;
+        }
+        let position = room_transform.translation.xy() + STAIRS_OFFSET;
+        commands.spawn((
+            SpriteBundle {
+                texture: asset_server.load("square.png"),
+                sprite: Sprite {
+                    color: Color::rgb(0.1, 0.1, 0.1),
+                    custom_size: Some(Vec2::splat(STAIRS_SIZE)),
+                    ..default()
+                },
+                transform: Transform::from_translation(position.extend(-1.)),
+                ..default()
+            },
+            Stairs,
+            Name::new("Stairs"),
+        ));
+        info!("Stairs opened! Transform: {:?}", position);
+    }
+}
+
+fn take_stairs(
+    players: Query<&Transform, With<Player>>,
+    stairs: Query<&Transform, With<Stairs>>,
+    mut next_floor: EventWriter<NextFloor>,
+) {
+    let Ok(player) = players.get_single() else {
+        return;
+    };
+    if stairs
+        .iter()
+        .any(|stairs| stairs.translation.xy().distance(player.translation.xy()) < STAIRS_RADIUS)
+    {
+        next_floor.send(NextFloor);
+    }
+}
+
+// Entities that belong to one floor and go with it
+type FloorEntities = Or<(
+    With<RoomTag>,
+    With<CorridorTag>,
+    With<Stairs>,
+    With<EnemySpawner>,
+)>;
+// Parents that outlive the floor, but not their children
+type FloorParents = Or<(
+    With<WallParent>,
+    With<EnemyParent>,
+    With<DropsParent>,
+    With<ProjectileParent>,
+)>;
+
+// Clears out everything belonging to the current floor. The player entity and `Money` are kept,
+// so health and anything picked up carry over. Bumping `DungeonFloor` makes map generation run
+// again
+fn change_floor(
+    mut commands: Commands,
+    mut next_floor: EventReader<NextFloor>,
+    mut floor: ResMut<DungeonFloor>,
+    mut encounter: ResMut<Encounter>,
+    entities: Query<Entity, FloorEntities>,
+    parents: Query<Entity, FloorParents>,
+) {
+    if next_floor.read().count() == 0 {
+        return;
+    }
+
+    for entity in &entities {
+        commands.entity(entity).despawn_recursive();
+    }
+    for parent in &parents {
+        commands.entity(parent).despawn_descendants();
+    }
+    encounter.room = None;
+
+    floor.0 += 1;
+    info!("Descending to floor {}", floor.0);
+}
@@ -0,0 +1,146 @@
+use bevy::prelude::*;
+
+use crate::main_menu::GameState;
+use crate::map_gen::{CorridorFloor, RoomTag};
+use crate::player::Player;
+
+// Explored rooms keep this much of their colour
+const EXPLORED_BRIGHTNESS: f32 = 0.35;
+const EXPLORED_ALPHA: f32 = 0.75;
+// Rooms the player isn't in are drawn over what's in them, but under the walls
+const COVER_Z: f32 = 5.;
+// The current room is drawn under the floor tiles, so only its outline shows through
+const CURRENT_Z: f32 = -30.;
+
+pub struct FogPlugin;
+
+impl Plugin for FogPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (
+                init_room_visibility,
+                update_room_visibility,
+                explore_corridors,
+                show_room_visibility,
+            )
+                .chain()
+                .run_if(in_state(GameState::Game)),
+        )
+        .register_type::<RoomVisibility>();
+    }
+}
+
+// How much the player knows about a room. Rooms start unseen and stay explored once they've been
+// entered, until the floor is left
+#[derive(Component, Default, Clone, Copy, Debug, PartialEq, Eq, Reflect)]
+#[reflect(Component)]
+pub enum RoomVisibility {
+    #[default]
+    Unseen,
+    Explored,
+    Current,
+}
+
+// The materials a room switches between, so its own colour is never lost
+#[derive(Component)]
+struct RoomMaterials {
+    original: Handle<ColorMaterial>,
+    explored: Handle<ColorMaterial>,
+    unseen: Handle<ColorMaterial>,
+}
+
+fn init_room_visibility(
+    mut commands: Commands,
+    rooms: Query<(Entity, &Handle<ColorMaterial>), Added<RoomTag>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut unseen: Local<Option<Handle<ColorMaterial>>>,
+) {
+    let unseen = unseen
+        .get_or_insert_with(|| materials.add(ColorMaterial::from(Color::BLACK)))
+        .clone();
+    for (room_entity, material) in &rooms {
+        let color = materials
+            .get(material)
+            .map_or(Color::BLACK, |material| material.color);
+        let explored = Color::rgba(
+            color.r() * EXPLORED_BRIGHTNESS,
+            color.g() * EXPLORED_BRIGHTNESS,
+            color.b() * EXPLORED_BRIGHTNESS,
+            EXPLORED_ALPHA,
+        );
+        commands.entity(room_entity).insert((
+            RoomVisibility::Unseen,
+            RoomMaterials {
+                original: material.clone(),
+                explored: materials.add(ColorMaterial::from(explored)),
+                unseen: unseen.clone(),
+            },
+        ));
+    }
+}
+
+// Makes the room the player is standing in current, and the one they left explored
+fn update_room_visibility(
+    players: Query<&Transform, With<Player>>,
+    mut rooms: Query<(&Transform, &RoomTag, &mut RoomVisibility)>,
+) {
+    let Ok(player) = players.get_single() else {
+        return;
+    };
+    for (room_transform, room, mut visibility) in &mut rooms {
+        let inside = room
+            .rect(room_transform.translation)
+            .contains(player.translation.xy());
+        let next = match (*visibility, inside) {
+            (_, true) => RoomVisibility::Current,
+            (RoomVisibility::Current, false) => RoomVisibility::Explored,
+            (state, false) => state,
+        };
+        if *visibility != next {
+            if next == RoomVisibility::Current {
+                info!(
+                    "Entered the room! Transform: {:?}",
+                    room_transform.translation
+                );
+            }
+            *visibility = next;
+        }
+    }
+}
+
+fn explore_corridors(
+    players: Query<&Transform, With<Player>>,
+    mut floors: Query<&mut CorridorFloor>,
+) {
+    let Ok(player) = players.get_single() else {
+        return;
+    };
+    for mut floor in &mut floors {
+        if !floor.explored && floor.rect.contains(player.translation.xy()) {
+            floor.explored = true;
+        }
+    }
+}
+
+fn show_room_visibility(
+    mut rooms: Query<
+        (
+            &mut Transform,
+            &mut Handle<ColorMaterial>,
+            &RoomMaterials,
+            &RoomVisibility,
+        ),
+        Changed<RoomVisibility>,
+    >,
+) {
+    for (mut transform, mut material, materials, visibility) in &mut rooms {
+        let (z, handle) = match visibility {
+            RoomVisibility::Unseen => (COVER_Z, &materials.unseen),
+            RoomVisibility::Explored => (COVER_Z, &materials.explored),
+            RoomVisibility::Current => (CURRENT_Z, &materials.original),
+        };
+        transform.translation.z = z;
+        *material = handle.clone();
+    }
+}
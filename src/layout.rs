@@ -0,0 +1,786 @@
+use bevy::math::{IVec2, Rect, Vec2};
+use bevy::reflect::Reflect;
+use bevy::utils::HashSet;
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+use crate::map_file::MapTile;
+use crate::tile_grid::TileGrid;
+use crate::tilemap::TILE_SIZE;
+
+pub const MIN_LEAF_SIZE: f32 = 200.;
+pub const MAX_LEAF_SIZE: f32 = 600.;
+pub const DOOR_WIDTH: f32 = TILE_SIZE * 3.;
+// Two rooms only get a door if their shared edge is long enough to fit the door plus a wall
+// tile on each side of it
+const MIN_SHARED_EDGE: f32 = DOOR_WIDTH + TILE_SIZE * 2.;
+// With corridors on, rooms keep at least this many tiles clear on each side of their leaf, so
+// there's space for a corridor to run between two rooms
+const LEAF_PADDING: f32 = 3.;
+// Smallest share of its leaf's width and height a room shrinks down to
+const MIN_ROOM_FILL: f32 = 0.6;
+// Tiles of floor on each side of a corridor's centre line
+const CORRIDOR_HALF_WIDTH: i32 = 1;
+
+// Plain data describing a generated dungeon. Nothing in here touches the ECS, so a layout can be
+// built and inspected without an `App`; `map_gen::start_level` is what turns it into entities.
+#[derive(Clone, Debug, Default)]
+pub struct DungeonLayout {
+    pub bounds: Rect,
+    // Every leaf of the BSP tree, including the ones that were split further
+    pub leaves: Vec<Leaf>,
+    pub rooms: Vec<Room>,
+    // Room graph: indices into `rooms` of the rooms each room has a door to
+    pub adjacency: Vec<Vec<usize>>,
+    pub corridors: Vec<Corridor>,
+    // Tiles laid down by generators that work cell by cell. Rooms, features and corridors are
+    // drawn over these by `TileGrid::from_layout`
+    pub tiles: Option<TileGrid>,
+}
+
+#[derive(Clone, Copy, Debug, Default, Reflect)]
+pub struct Leaf {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+    pub child_split: bool,
+    // Indices into `DungeonLayout::leaves` of the two halves this leaf was split into
+    pub children: Option<(usize, usize)>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Room {
+    // Index into `DungeonLayout::leaves` of the leaf this room fills
+    pub leaf: usize,
+    pub rect: Rect,
+    pub is_left_edge: bool,
+    pub is_right_edge: bool,
+    pub is_top_edge: bool,
+    pub is_bottom_edge: bool,
+    pub doors: Vec<Vec2>,
+    pub kind: RoomKind,
+    // Obstacles, pits, enemy spawn points and drops placed inside the room, in world space
+    pub features: Vec<(Vec2, MapTile)>,
+    // Whether the room gets walls around its edges. Cave rooms are open areas whose walls come
+    // from `DungeonLayout::tiles` instead
+    pub walled: bool,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RoomKind {
+    #[default]
+    Normal,
+    // Where the player spawns, never has an encounter
+    Start,
+    // The room furthest from the start, counted in doors
+    Boss,
+    Shop,
+    Treasure,
+}
+
+// A walkway joining two rooms that don't touch. Corridors run from a door of one room to a door
+// of the other, turning at most once
+#[derive(Clone, Debug)]
+pub struct Corridor {
+    // Centre line, starting and ending on the rooms' edges
+    pub path: Vec<Vec2>,
+    // Floor covered by each straight stretch of the corridor, including its doorways
+    pub floor: Vec<Rect>,
+    // Wall tiles down both sides. Tiles another corridor needs as floor are left out, so
+    // crossing corridors stay open
+    pub walls: Vec<Vec2>,
+}
+
+impl DungeonLayout {
+    pub fn builder() -> DungeonLayoutBuilder {
+        DungeonLayoutBuilder::default()
+    }
+
+    // Number of doors that have to be walked through to reach each room from `start`, or `None`
+    // for rooms that can't be reached at all
+    pub fn distances_from(&self, start: usize) -> Vec<Option<usize>> {
+        let mut distances = vec![None; self.rooms.len()];
+        let mut queue = std::collections::VecDeque::new();
+        distances[start] = Some(0);
+        queue.push_back(start);
+        while let Some(room) = queue.pop_front() {
+            let distance = distances[room].unwrap();
+            for &next in &self.adjacency[room] {
+                if distances[next].is_none() {
+                    distances[next] = Some(distance + 1);
+                    queue.push_back(next);
+                }
+            }
+        }
+        distances
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.rooms.is_empty() || self.distances_from(0).iter().all(Option::is_some)
+    }
+
+    // Roles are handed out in order of importance, so a dungeon with only a couple of rooms
+    // still gets a start and a boss before any shop or treasure
+    pub fn assign_room_kinds(&mut self, rng: &mut impl Rng, treasure_rooms: usize) {
+        if self.rooms.len() < 2 {
+            return;
+        }
+
+        let start = rng.gen_range(0..self.rooms.len());
+        self.rooms[start].kind = RoomKind::Start;
+
+        let distances = self.distances_from(start);
+        // Even if nothing else is reachable, the boss never lands on the start room
+        let boss = (0..self.rooms.len())
+            .filter(|&room| room != start)
+            .max_by_key(|&room| distances[room].unwrap_or(0))
+            .unwrap();
+        self.rooms[boss].kind = RoomKind::Boss;
+
+        // Treasure goes in dead ends first, so it's a detour to go and get it
+        let mut free: Vec<usize> = (0..self.rooms.len())
+            .filter(|&room| self.rooms[room].kind == RoomKind::Normal)
+            .collect();
+        free.shuffle(rng);
+        free.sort_by_key(|&room| self.adjacency[room].len() != 1);
+
+        let mut free = free.into_iter();
+        for room in free.by_ref().take(treasure_rooms) {
+            self.rooms[room].kind = RoomKind::Treasure;
+        }
+        if let Some(room) = free.next() {
+            self.rooms[room].kind = RoomKind::Shop;
+        }
+    }
+
+    fn rooms_under(&self, leaf: usize) -> Vec<usize> {
+        match self.leaves[leaf].children {
+            Some((left, right)) => {
+                let mut rooms = self.rooms_under(left);
+                rooms.extend(self.rooms_under(right));
+                rooms
+            }
+            None => self
+                .rooms
+                .iter()
+                .position(|room| room.leaf == leaf)
+                .into_iter()
+                .collect(),
+        }
+    }
+
+    fn add_door(&mut self, a: usize, b: usize, position: Vec2) {
+        self.rooms[a].doors.push(position);
+        self.rooms[b].doors.push(position);
+        self.adjacency[a].push(b);
+        self.adjacency[b].push(a);
+    }
+
+    fn add_corridor(&mut self, a: usize, b: usize, path: Vec<Vec2>) {
+        let (start, end) = (path[0], path[path.len() - 1]);
+        self.rooms[a].doors.push(start);
+        self.rooms[b].doors.push(end);
+        self.adjacency[a].push(b);
+        self.adjacency[b].push(a);
+
+        let grow = (CORRIDOR_HALF_WIDTH as f32 + 0.5) * TILE_SIZE;
+        let floor = path
+            .windows(2)
+            .map(|segment| Rect::from_corners(segment[0], segment[1]).inset(grow))
+            .collect();
+        self.corridors.push(Corridor {
+            path,
+            floor,
+            walls: Vec::new(),
+        });
+    }
+
+    // Walls go on every tile next to a corridor that isn't corridor floor or part of a room
+    fn build_corridor_walls(&mut self) {
+        let origin = self.bounds.min;
+        let tiles = |corridor: &Corridor, radius: i32| {
+            let mut tiles = HashSet::new();
+            for point in corridor_points(&corridor.path) {
+                let tile = ((point - origin) / TILE_SIZE).round().as_ivec2();
+                for y in -radius..=radius {
+                    for x in -radius..=radius {
+                        tiles.insert(tile + IVec2::new(x, y));
+                    }
+                }
+            }
+            tiles
+        };
+
+        let mut taken: HashSet<IVec2> = self
+            .corridors
+            .iter()
+            .flat_map(|corridor| tiles(corridor, CORRIDOR_HALF_WIDTH))
+            .collect();
+        for i in 0..self.corridors.len() {
+            let mut walls: Vec<IVec2> = tiles(&self.corridors[i], CORRIDOR_HALF_WIDTH + 1)
+                .into_iter()
+                .filter(|tile| !taken.contains(tile))
+                .collect();
+            walls.sort_by_key(|tile| (tile.y, tile.x));
+            self.corridors[i].walls = walls
+                .into_iter()
+                .filter(|&tile| taken.insert(tile))
+                .map(|tile| origin + tile.as_vec2() * TILE_SIZE)
+                .filter(|&position| {
+                    self.rooms
+                        .iter()
+                        .all(|room| rect_distance(room.rect, position) > TILE_SIZE / 2.)
+                })
+                .collect();
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct DungeonLayoutBuilder {
+    bounds: Rect,
+    min_leaf_size: f32,
+    max_leaf_size: f32,
+    // Chance for each pair of touching rooms that the BSP tree didn't already connect to get a
+    // door anyway, which adds loops to the otherwise tree-shaped room graph
+    extra_door_chance: f64,
+    treasure_rooms: usize,
+    corridors: bool,
+}
+
+impl Default for DungeonLayoutBuilder {
+    fn default() -> Self {
+        DungeonLayoutBuilder {
+            bounds: default_bounds(),
+            min_leaf_size: MIN_LEAF_SIZE,
+            max_leaf_size: MAX_LEAF_SIZE,
+            extra_door_chance: 0.15,
+            treasure_rooms: 2,
+            corridors: false,
+        }
+    }
+}
+
+impl DungeonLayoutBuilder {
+    // Shrinks every room inside its leaf and joins them with corridors instead of doors in
+    // shared walls
+    pub fn corridors(mut self, corridors: bool) -> Self {
+        self.corridors = corridors;
+        self
+    }
+
+    pub fn build(&self, rng: &mut impl Rng) -> DungeonLayout {
+        let leaves = self.split_leaves(rng);
+
+        let rooms: Vec<Room> = leaves
+            .iter()
+            .enumerate()
+            .filter(|(_, l)| !l.child_split)
+            .map(|(i, l)| {
+                let rect = if self.corridors {
+                    self.shrink_room(l.rect(), rng)
+                } else {
+                    l.rect()
+                };
+                // Shrunk rooms don't share walls with anything, so every side is an outside edge
+                Room {
+                    leaf: i,
+                    rect,
+                    is_left_edge: self.corridors || rect.min.x <= self.bounds.min.x,
+                    is_right_edge: self.corridors || rect.max.x >= self.bounds.max.x,
+                    is_top_edge: self.corridors || rect.max.y >= self.bounds.max.y,
+                    is_bottom_edge: self.corridors || rect.min.y <= self.bounds.min.y,
+                    doors: Vec::new(),
+                    kind: RoomKind::Normal,
+                    features: Vec::new(),
+                    walled: true,
+                }
+            })
+            .collect();
+
+        let room_count = rooms.len();
+        let mut layout = DungeonLayout {
+            bounds: self.bounds,
+            leaves,
+            rooms,
+            adjacency: vec![Vec::new(); room_count],
+            corridors: Vec::new(),
+            tiles: None,
+        };
+
+        if self.corridors {
+            self.connect_with_corridors(&mut layout);
+            layout.assign_room_kinds(rng, self.treasure_rooms);
+            return layout;
+        }
+
+        // Connecting the two halves of every split joins the whole tree, so every room is
+        // reachable from every other one
+        for leaf in 0..layout.leaves.len() {
+            let Some((left, right)) = layout.leaves[leaf].children else {
+                continue;
+            };
+            let left_rooms = layout.rooms_under(left);
+            let right_rooms = layout.rooms_under(right);
+            let mut candidates = Vec::new();
+            for &a in &left_rooms {
+                for &b in &right_rooms {
+                    if let Some(edge) = shared_edge(layout.rooms[a].rect, layout.rooms[b].rect) {
+                        candidates.push((a, b, edge));
+                    }
+                }
+            }
+            let wide_enough: Vec<_> = candidates
+                .iter()
+                .copied()
+                .filter(|&(_, _, edge)| edge_length(edge) >= MIN_SHARED_EDGE)
+                .collect();
+            let chosen = match wide_enough.choose(rng) {
+                Some(&candidate) => Some(candidate),
+                // Fall back to the widest edge rather than leaving half of the tree sealed off
+                None => candidates
+                    .into_iter()
+                    .max_by(|x, y| edge_length(x.2).total_cmp(&edge_length(y.2))),
+            };
+            if let Some((a, b, edge)) = chosen {
+                layout.add_door(a, b, snap_to_tile((edge.0 + edge.1) / 2., self.bounds.min));
+            }
+        }
+
+        for a in 0..room_count {
+            for b in a + 1..room_count {
+                if layout.adjacency[a].contains(&b) {
+                    continue;
+                }
+                let Some(edge) = shared_edge(layout.rooms[a].rect, layout.rooms[b].rect) else {
+                    continue;
+                };
+                if edge_length(edge) >= MIN_SHARED_EDGE && rng.gen_bool(self.extra_door_chance) {
+                    layout.add_door(a, b, snap_to_tile((edge.0 + edge.1) / 2., self.bounds.min));
+                }
+            }
+        }
+
+        layout.assign_room_kinds(rng, self.treasure_rooms);
+        layout
+    }
+
+    // A random rectangle of whole tiles inside `leaf`, keeping `LEAF_PADDING` tiles clear
+    fn shrink_room(&self, leaf: Rect, rng: &mut impl Rng) -> Rect {
+        let origin = self.bounds.min;
+        let first = ((leaf.min - origin) / TILE_SIZE + LEAF_PADDING).ceil();
+        let last = ((leaf.max - origin) / TILE_SIZE - LEAF_PADDING).floor();
+        let space = last - first;
+        // No room to pad a leaf this small, so the room takes up all of it
+        if space.x < 1. || space.y < 1. {
+            return leaf;
+        }
+        let smallest = (leaf.size() / TILE_SIZE * MIN_ROOM_FILL).ceil().min(space);
+
+        let size = Vec2::new(
+            rng.gen_range(smallest.x..=space.x).round(),
+            rng.gen_range(smallest.y..=space.y).round(),
+        );
+        let min = Vec2::new(
+            rng.gen_range(first.x..=last.x - size.x).round(),
+            rng.gen_range(first.y..=last.y - size.y).round(),
+        );
+        Rect::from_corners(origin + min * TILE_SIZE, origin + (min + size) * TILE_SIZE)
+    }
+
+    // Joins the two halves of every split with a corridor between the closest pair of rooms that
+    // one can be dug between without running into any other room
+    fn connect_with_corridors(&self, layout: &mut DungeonLayout) {
+        for leaf in 0..layout.leaves.len() {
+            let Some((left, right)) = layout.leaves[leaf].children else {
+                continue;
+            };
+            let left_rooms = layout.rooms_under(left);
+            let right_rooms = layout.rooms_under(right);
+
+            let mut candidates = Vec::new();
+            for &a in &left_rooms {
+                for &b in &right_rooms {
+                    for path in
+                        corridor_paths(layout.rooms[a].rect, layout.rooms[b].rect, self.bounds.min)
+                    {
+                        let clear = corridor_is_clear(layout, a, b, &path);
+                        candidates.push((a, b, path, clear));
+                    }
+                }
+            }
+            // Cutting through a room is still better than leaving half of the tree sealed off
+            let clear = candidates.iter().any(|candidate| candidate.3);
+            let chosen = candidates
+                .into_iter()
+                .filter(|candidate| candidate.3 || !clear)
+                .min_by(|x, y| path_length(&x.2).total_cmp(&path_length(&y.2)));
+            if let Some((a, b, path, _)) = chosen {
+                layout.add_corridor(a, b, path);
+            }
+        }
+        layout.build_corridor_walls();
+    }
+
+    fn split_leaves(&self, rng: &mut impl Rng) -> Vec<Leaf> {
+        let mut leaves: Vec<Leaf> = vec![];
+        let root = Leaf {
+            x: self.bounds.min.x,
+            y: self.bounds.min.y,
+            width: self.bounds.width(),
+            height: self.bounds.height(),
+            child_split: false,
+            children: None,
+        };
+        leaves.push(root);
+        let mut fully_split = false;
+        while !fully_split {
+            fully_split = true;
+            let mut new_leaves: Vec<Leaf> = vec![];
+            let first_new = leaves.len();
+            for l in leaves.iter_mut() {
+                if l.child_split {
+                    continue;
+                }
+                if l.width > self.max_leaf_size || l.height > self.max_leaf_size {
+                    if let Some(leaf) = l.split(rng, self.min_leaf_size) {
+                        let index = first_new + new_leaves.len();
+                        l.children = Some((index, index + 1));
+                        new_leaves.push(leaf.0);
+                        new_leaves.push(leaf.1);
+                        fully_split = false;
+                    }
+                }
+            }
+            leaves.append(&mut new_leaves)
+        }
+        leaves
+    }
+}
+
+impl Leaf {
+    pub fn rect(&self) -> Rect {
+        Rect::new(self.x, self.y, self.x + self.width, self.y + self.height)
+    }
+
+    fn split(&mut self, rng: &mut impl Rng, min_leaf_size: f32) -> Option<(Leaf, Leaf)> {
+        if self.child_split {
+            return None;
+        }
+
+        let max: f32;
+        let mut split_b: bool = rng.gen_bool(0.5);
+        if (self.width > self.height) && (self.width / self.height >= 1.25) {
+            max = self.width - min_leaf_size;
+            split_b = false;
+        } else if (self.height > self.width) && (self.height / self.width >= 1.25) {
+            max = self.height - min_leaf_size;
+            split_b = true;
+        } else {
+            max = if split_b {
+                self.height - min_leaf_size
+            } else {
+                self.width - min_leaf_size
+            }
+        }
+        if max <= min_leaf_size {
+            return None;
+        }
+
+        // Whole tiles only, so every room edge lines up with the tile grid
+        let split = (rng.gen_range(min_leaf_size..max) / TILE_SIZE).round() * TILE_SIZE;
+        let left_child;
+        let right_child;
+        /*
+        divide by height
+        */
+        if split_b {
+            left_child = Leaf {
+                x: self.x,
+                y: self.y,
+                width: self.width,
+                height: split,
+                child_split: false,
+                children: None,
+            };
+            right_child = Leaf {
+                x: self.x,
+                y: self.y + split,
+                width: self.width,
+                height: self.height - split,
+                child_split: false,
+                children: None,
+            };
+
+        /*
+        divide by width
+        */
+        } else {
+            left_child = Leaf {
+                x: self.x,
+                y: self.y,
+                width: split,
+                height: self.height,
+                child_split: false,
+                children: None,
+            };
+            right_child = Leaf {
+                x: self.x + split,
+                y: self.y,
+                width: self.width - split,
+                height: self.height,
+                child_split: false,
+                children: None,
+            };
+        }
+        self.child_split = true;
+        Some((left_child, right_child))
+    }
+}
+
+// Area every generator lays its dungeon out in unless told otherwise
+pub fn default_bounds() -> Rect {
+    let weight = 2.;
+    Rect::new(-640. * weight, -360. * weight, 640. * weight, 360. * weight)
+}
+
+// Possible centre lines for a corridor from `a` to `b`: straight across where the rooms face each
+// other, or an L leaving one room from the side and the other from the top or bottom
+fn corridor_paths(a: Rect, b: Rect, origin: Vec2) -> Vec<Vec<Vec2>> {
+    let snap = |v: Vec2| snap_to_tile(v, origin);
+    // Keeps doors clear of the rooms' corners
+    let margin = TILE_SIZE * 2.;
+    let mut paths = Vec::new();
+
+    let (bottom, top) = (a.min.y.max(b.min.y) + margin, a.max.y.min(b.max.y) - margin);
+    if bottom <= top {
+        let y = snap(Vec2::new(0., (bottom + top) / 2.)).y;
+        if b.min.x >= a.max.x {
+            paths.push(vec![Vec2::new(a.max.x, y), Vec2::new(b.min.x, y)]);
+        } else if a.min.x >= b.max.x {
+            paths.push(vec![Vec2::new(a.min.x, y), Vec2::new(b.max.x, y)]);
+        }
+    }
+    let (left, right) = (a.min.x.max(b.min.x) + margin, a.max.x.min(b.max.x) - margin);
+    if left <= right {
+        let x = snap(Vec2::new((left + right) / 2., 0.)).x;
+        if b.min.y >= a.max.y {
+            paths.push(vec![Vec2::new(x, a.max.y), Vec2::new(x, b.min.y)]);
+        } else if a.min.y >= b.max.y {
+            paths.push(vec![Vec2::new(x, a.min.y), Vec2::new(x, b.max.y)]);
+        }
+    }
+
+    for (from, to, reversed) in [(a, b, false), (b, a, true)] {
+        // Out of the side of `from`, then up or down into `to`
+        let corner = snap(Vec2::new(to.center().x, from.center().y));
+        let side = if corner.x > from.center().x {
+            from.max.x
+        } else {
+            from.min.x
+        };
+        let end = if corner.y < to.min.y {
+            to.min.y
+        } else if corner.y > to.max.y {
+            to.max.y
+        } else {
+            continue;
+        };
+        let mut path = vec![Vec2::new(side, corner.y), corner, Vec2::new(corner.x, end)];
+        if reversed {
+            path.reverse();
+        }
+        paths.push(path);
+    }
+    paths
+}
+
+// Whether a corridor keeps its floor and walls clear of every room other than where it meets
+// its own two rooms' doors
+fn corridor_is_clear(layout: &DungeonLayout, a: usize, b: usize, path: &[Vec2]) -> bool {
+    let points = corridor_points(path);
+    let clearance = TILE_SIZE * (CORRIDOR_HALF_WIDTH + 1) as f32 - 0.5;
+    let bounds = layout.bounds.inset(-clearance);
+    points.iter().enumerate().all(|(i, &point)| {
+        bounds.contains(point)
+            && layout.rooms.iter().enumerate().all(|(room, r)| {
+                // The ends of the corridor have to get close to their own rooms
+                let near_end = (room == a && i <= CORRIDOR_HALF_WIDTH as usize + 1)
+                    || (room == b && points.len() - 1 - i <= CORRIDOR_HALF_WIDTH as usize + 1);
+                near_end || rect_distance(r.rect, point) >= clearance
+            })
+    })
+}
+
+// Every tile along a corridor's centre line
+fn corridor_points(path: &[Vec2]) -> Vec<Vec2> {
+    let mut points = vec![path[0]];
+    for segment in path.windows(2) {
+        let steps = (segment[0].distance(segment[1]) / TILE_SIZE).round() as usize;
+        for step in 1..=steps {
+            points.push(segment[0].lerp(segment[1], step as f32 / steps as f32));
+        }
+    }
+    points
+}
+
+fn path_length(path: &[Vec2]) -> f32 {
+    path.windows(2)
+        .map(|segment| segment[0].distance(segment[1]))
+        .sum()
+}
+
+// Nearest tile centre to `point`, for a grid of tiles centred on `origin`
+fn snap_to_tile(point: Vec2, origin: Vec2) -> Vec2 {
+    origin + ((point - origin) / TILE_SIZE).round() * TILE_SIZE
+}
+
+// How far a point is outside a rectangle, measured along whichever axis is furthest. Zero on or
+// inside it
+fn rect_distance(rect: Rect, point: Vec2) -> f32 {
+    let outside = (rect.min - point).max(point - rect.max).max(Vec2::ZERO);
+    outside.x.max(outside.y)
+}
+
+fn edge_length(edge: (Vec2, Vec2)) -> f32 {
+    edge.0.distance(edge.1)
+}
+
+// Returns the end points of the edge segment two rooms have in common, if they touch
+fn shared_edge(a: Rect, b: Rect) -> Option<(Vec2, Vec2)> {
+    // Tolerance for the float coordinates of neighbouring leaves
+    let tolerance = 1.0;
+
+    let x = if (a.max.x - b.min.x).abs() <= tolerance {
+        Some(a.max.x)
+    } else if (b.max.x - a.min.x).abs() <= tolerance {
+        Some(a.min.x)
+    } else {
+        None
+    };
+    if let Some(x) = x {
+        let bottom = a.min.y.max(b.min.y);
+        let top = a.max.y.min(b.max.y);
+        if top - bottom > tolerance {
+            return Some((Vec2::new(x, bottom), Vec2::new(x, top)));
+        }
+    }
+
+    let y = if (a.max.y - b.min.y).abs() <= tolerance {
+        Some(a.max.y)
+    } else if (b.max.y - a.min.y).abs() <= tolerance {
+        Some(a.min.y)
+    } else {
+        None
+    };
+    if let Some(y) = y {
+        let left = a.min.x.max(b.min.x);
+        let right = a.max.x.min(b.max.x);
+        if right - left > tolerance {
+            return Some((Vec2::new(left, y), Vec2::new(right, y)));
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::*;
+
+    const SEEDS: [u64; 6] = [0, 1, 7, 42, 1234, 987654321];
+
+    fn layouts(builder: DungeonLayoutBuilder) -> impl Iterator<Item = DungeonLayout> {
+        SEEDS
+            .into_iter()
+            .map(move |seed| builder.build(&mut StdRng::seed_from_u64(seed)))
+    }
+
+    fn builders() -> [DungeonLayoutBuilder; 2] {
+        [
+            DungeonLayout::builder(),
+            DungeonLayout::builder().corridors(true),
+        ]
+    }
+
+    #[test]
+    fn every_room_is_reachable() {
+        for builder in builders() {
+            for layout in layouts(builder) {
+                assert!(layout.rooms.len() > 1);
+                assert!(layout.is_connected());
+            }
+        }
+    }
+
+    #[test]
+    fn rooms_do_not_overlap() {
+        for builder in builders() {
+            for layout in layouts(builder) {
+                for (i, a) in layout.rooms.iter().enumerate() {
+                    for b in &layout.rooms[i + 1..] {
+                        // Neighbouring rooms share an edge, which is an empty intersection
+                        assert!(a.rect.intersect(b.rect).is_empty());
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn leaves_stay_within_size_limits() {
+        for layout in layouts(DungeonLayout::builder()) {
+            for leaf in &layout.leaves {
+                assert!(leaf.width >= MIN_LEAF_SIZE && leaf.height >= MIN_LEAF_SIZE);
+                if !leaf.child_split {
+                    assert!(leaf.width <= MAX_LEAF_SIZE && leaf.height <= MAX_LEAF_SIZE);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rooms_fit_inside_their_leaves() {
+        for builder in builders() {
+            for layout in layouts(builder) {
+                for room in &layout.rooms {
+                    let leaf = layout.leaves[room.leaf].rect();
+                    assert!(!layout.leaves[room.leaf].child_split);
+                    assert_eq!(leaf.union(room.rect), leaf);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn boss_room_is_not_the_start_room() {
+        for builder in builders() {
+            for layout in layouts(builder) {
+                let kind = |kind| layout.rooms.iter().filter(|room| room.kind == kind).count();
+                assert_eq!(kind(RoomKind::Start), 1);
+                assert_eq!(kind(RoomKind::Boss), 1);
+            }
+        }
+    }
+
+    #[test]
+    fn leaves_too_small_to_pad_still_get_rooms() {
+        let builder = DungeonLayoutBuilder {
+            bounds: Rect::new(0., 0., TILE_SIZE * 48., TILE_SIZE * 48.),
+            min_leaf_size: TILE_SIZE * 4.,
+            max_leaf_size: TILE_SIZE * 8.,
+            ..DungeonLayout::builder().corridors(true)
+        };
+        for layout in layouts(builder) {
+            for room in &layout.rooms {
+                let leaf = layout.leaves[room.leaf].rect();
+                assert!(!room.rect.is_empty());
+                assert_eq!(leaf.union(room.rect), leaf);
+            }
+        }
+    }
+}
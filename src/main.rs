@@ -1,15 +1,27 @@
 use crate::player::Player;
-use bevy::{prelude::*, render::camera::ScalingMode, input::common_conditions::input_toggle_active};
+use bevy::prelude::*;
 use bevy_inspector_egui::quick::WorldInspectorPlugin;
+use camera::GameCameraPlugin;
+use controls::{action_toggle_active, Action, ControlsPlugin};
+use collision::CollisionPlugin;
 use drops::DropsPlugin;
+use encounter::EncounterPlugin;
+use floor::FloorPlugin;
+use fog::FogPlugin;
 use enemy::EnemyPlugin;
 use enemy_spawner::EnemySpawnerPlugin;
 use pig::PigPlugin;
 use player::PlayerPlugin;
 use projectile::ProjectilePlugin;
+use tile_grid::TileGridPlugin;
+use tileset::TilesetPlugin;
 use tilemap::TileMapPlugin;
+use map_file::MapFilePlugin;
 use map_gen::MapGenPlugin;
 use main_menu::MainMenuPlugin;
+use seed::SeedPlugin;
+use wall::WallPlugin;
+use weapon::WeaponPlugin;
 
 use ui::GameUI;
 
@@ -17,16 +29,31 @@ use ui::GameUI;
 #[reflect(Resource)]
 pub struct Money(pub f32);
 
+mod camera;
+mod collision;
+mod controls;
 mod drops;
+mod encounter;
+mod floor;
+mod fog;
 mod enemy;
 mod enemy_spawner;
 mod pig;
 mod player;
 mod projectile;
+mod room_templates;
+mod tile_grid;
 mod tilemap;
+mod tileset;
 mod ui;
+mod map_file;
 mod map_gen;
+mod map_generator;
+mod layout;
 mod main_menu;
+mod seed;
+mod wall;
+mod weapon;
 
 fn main() {
     App::new()
@@ -44,7 +71,7 @@ fn main() {
                 .build(),
         )
                 .add_plugins(
-            WorldInspectorPlugin::default().run_if(input_toggle_active(true, KeyCode::Escape)),
+            WorldInspectorPlugin::default().run_if(action_toggle_active(true, Action::ToggleInspector)),
         )
         .insert_resource(ClearColor(Color::rgb(0.9, 0.3, 0.6)))
         .insert_resource(Money(100.0))
@@ -60,24 +87,35 @@ fn main() {
             TileMapPlugin,
             MapGenPlugin,
             MainMenuPlugin,
+            SeedPlugin,
+            WallPlugin,
+            EncounterPlugin,
+            MapFilePlugin,
+            FloorPlugin,
+        ))
+        .add_plugins((
+            TileGridPlugin,
+            TilesetPlugin,
+            FogPlugin,
+            GameCameraPlugin,
+            CollisionPlugin,
+            ControlsPlugin,
+            WeaponPlugin,
         ))
-        .add_systems(Startup, setup)
         .run();
 }
 
-fn setup(
-    mut commands: Commands,
-/*     mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<ColorMaterial>>, */
-) {
-    let mut camera = Camera2dBundle::default();
-
-    camera.projection.scaling_mode = ScalingMode::AutoMin {
-        min_width: 256.0,
-        min_height: 144.0,
-    };
-
-    commands.spawn(camera);
-
-
+// Value of `--name <value>` or `--name=<value>` on the command line
+pub fn cli_arg(name: &str) -> Option<String> {
+    let flag = format!("--{}", name);
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == flag {
+            return args.next();
+        }
+        if let Some(value) = arg.strip_prefix(&format!("{}=", flag)) {
+            return Some(value.to_string());
+        }
+    }
+    None
 }
@@ -30,8 +30,9 @@ enum DisplayQuality {
 struct Volume(u32);
 
 mod game {
-    use crate::enemy_spawner;
-    use crate::player::Player;
+    use crate::player::{player_collider, Player};
+    use crate::projectile::ProjectileModifiers;
+    use crate::weapon::Weapon;
 
     use super::{despawn_screen, GameState};
     use bevy::{prelude::*, sprite::MaterialMesh2dBundle};
@@ -76,26 +77,29 @@ mod game {
             Player {
                 speed: 50.0,
                 health: 200.0,
+                damage_multiplier: 1.0,
             },
+            player_collider(),
+            Weapon::default(),
+            // Added to by pickups, on top of whatever the weapon does
+            ProjectileModifiers::default(),
             Name::new("Player"),
         ));
-
-        commands.spawn(enemy_spawner::EnemySpawner {
-            cooldown: 1.,
-            timer: 1.,
-        });
     }
 }
 
 mod menu {
-    use bevy::{app::AppExit, prelude::*};
+    use bevy::{app::AppExit, prelude::*, ui::UiSystem, window::ReceivedCharacter};
 
     use super::{despawn_screen, DisplayQuality, GameState, Volume, TEXT_COLOR};
+    use crate::controls::{is_bindable, Action, InputMap};
+    use crate::seed::DungeonSeed;
 
     // This plugin manages the menu, with 5 different screens:
     // - a main menu with "New Game", "Settings", "Quit"
-    // - a settings menu with two submenus and a back button
+    // - a settings menu with three submenus and a back button
     // - two settings screen with a setting that can be set and a back button
+    // - a controls screen for rebinding keys
     pub struct MenuPlugin;
 
     impl Plugin for MenuPlugin {
@@ -141,7 +145,31 @@ mod menu {
                     OnExit(MenuState::SettingsSound),
                     despawn_screen::<OnSoundSettingsMenuScreen>,
                 )
+                // Systems to handle the controls screen
+                .init_resource::<Rebinding>()
+                .add_systems(OnEnter(MenuState::SettingsControls), controls_menu_setup)
+                .add_systems(
+                    Update,
+                    rebind_controls.run_if(in_state(MenuState::SettingsControls)),
+                )
+                .add_systems(
+                    OnExit(MenuState::SettingsControls),
+                    despawn_screen::<OnControlsMenuScreen>,
+                )
+                // Systems to handle the dungeon seed screen
+                .add_systems(OnEnter(MenuState::Seed), seed_menu_setup)
+                .add_systems(Update, seed_input.run_if(in_state(MenuState::Seed)))
+                .add_systems(OnExit(MenuState::Seed), despawn_screen::<OnSeedMenuScreen>)
                 // Common systems to all screens that handles buttons behavior
+                .init_resource::<GamepadFocus>()
+                // After the mouse has had its say, so every button system this frame sees
+                // gamepad presses
+                .add_systems(
+                    PreUpdate,
+                    gamepad_navigation
+                        .after(UiSystem::Focus)
+                        .run_if(in_state(GameState::Menu)),
+                )
                 .add_systems(
                     Update,
                     (menu_action, button_system).run_if(in_state(GameState::Menu)),
@@ -156,6 +184,8 @@ mod menu {
         Settings,
         SettingsDisplay,
         SettingsSound,
+        SettingsControls,
+        Seed,
         #[default]
         Disabled,
     }
@@ -176,6 +206,33 @@ mod menu {
     #[derive(Component)]
     struct OnSoundSettingsMenuScreen;
 
+    // Tag component used to tag entities added on the controls screen
+    #[derive(Component)]
+    struct OnControlsMenuScreen;
+
+    // Button that rebinds an action, showing the keys currently bound to it
+    #[derive(Component)]
+    struct RebindButton(Action);
+
+    // The action waiting for a key press to bind to it, if any
+    #[derive(Resource, Default)]
+    struct Rebinding(Option<Action>);
+
+    // Tag component used to tag entities added on the dungeon seed screen
+    #[derive(Component)]
+    struct OnSeedMenuScreen;
+
+    // Tag component used to mark the text displaying the current dungeon seed
+    #[derive(Component)]
+    struct SeedText;
+
+    // The button picked out with the d-pad, and the one the gamepad pressed last frame
+    #[derive(Resource, Default)]
+    struct GamepadFocus {
+        button: Option<Entity>,
+        pressed: Option<Entity>,
+    }
+
     const NORMAL_BUTTON: Color = Color::rgb(0.15, 0.15, 0.15);
     const HOVERED_BUTTON: Color = Color::rgb(0.25, 0.25, 0.25);
     const HOVERED_PRESSED_BUTTON: Color = Color::rgb(0.25, 0.65, 0.25);
@@ -192,6 +249,10 @@ mod menu {
         Settings,
         SettingsDisplay,
         SettingsSound,
+        SettingsControls,
+        ResetControls,
+        Seed,
+        RandomizeSeed,
         BackToMainMenu,
         BackToSettings,
         Quit,
@@ -214,6 +275,108 @@ mod menu {
         }
     }
 
+    // A button the d-pad can move focus to and press
+    type NavigableButton<'a> = (
+        Entity,
+        &'a GlobalTransform,
+        &'a mut Interaction,
+        &'a mut BackgroundColor,
+        Option<&'a SelectedOption>,
+        Option<&'a MenuButtonAction>,
+    );
+
+    // Moves between buttons with the d-pad, in reading order, presses the focused button with
+    // South (A on most pads) and goes back a screen with East (B)
+    fn gamepad_navigation(
+        gamepads: Res<Gamepads>,
+        pad: Res<Input<GamepadButton>>,
+        mut focus: ResMut<GamepadFocus>,
+        mut buttons: Query<NavigableButton, With<Button>>,
+    ) {
+        let just_pressed = |button_type| {
+            gamepads
+                .iter()
+                .any(|gamepad| pad.just_pressed(GamepadButton::new(gamepad, button_type)))
+        };
+
+        // A press only lasts a frame, the mouse isn't going to release it
+        if let Some(pressed) = focus.pressed.take() {
+            if let Ok((_, _, mut interaction, ..)) = buttons.get_mut(pressed) {
+                *interaction = Interaction::None;
+            }
+        }
+        // The focused button went with the screen it was on
+        if focus.button.is_some_and(|button| !buttons.contains(button)) {
+            focus.button = None;
+        }
+
+        let mut order: Vec<(Entity, Vec3)> = buttons
+            .iter()
+            .map(|(entity, transform, ..)| (entity, transform.translation()))
+            .collect();
+        order.sort_by(|(_, a), (_, b)| a.y.total_cmp(&b.y).then(a.x.total_cmp(&b.x)));
+
+        let step = if just_pressed(GamepadButtonType::DPadDown)
+            || just_pressed(GamepadButtonType::DPadRight)
+        {
+            1
+        } else if just_pressed(GamepadButtonType::DPadUp)
+            || just_pressed(GamepadButtonType::DPadLeft)
+        {
+            -1
+        } else {
+            0
+        };
+        if step != 0 && !order.is_empty() {
+            let next = match focus
+                .button
+                .and_then(|button| order.iter().position(|&(entity, _)| entity == button))
+            {
+                Some(index) => (index as i32 + step).rem_euclid(order.len() as i32) as usize,
+                None => 0,
+            };
+            if let Some(previous) = focus.button {
+                if let Ok((_, _, _, mut color, selected, _)) = buttons.get_mut(previous) {
+                    *color = if selected.is_some() {
+                        PRESSED_BUTTON.into()
+                    } else {
+                        NORMAL_BUTTON.into()
+                    };
+                }
+            }
+            focus.button = Some(order[next].0);
+            if let Ok((_, _, _, mut color, selected, _)) = buttons.get_mut(order[next].0) {
+                *color = if selected.is_some() {
+                    HOVERED_PRESSED_BUTTON.into()
+                } else {
+                    HOVERED_BUTTON.into()
+                };
+            }
+        }
+
+        let target = if just_pressed(GamepadButtonType::South) {
+            focus.button
+        } else if just_pressed(GamepadButtonType::East) {
+            buttons
+                .iter()
+                .find(|(.., action)| {
+                    matches!(
+                        action,
+                        Some(MenuButtonAction::BackToMainMenu | MenuButtonAction::BackToSettings)
+                    )
+                })
+                .map(|(entity, ..)| entity)
+        } else {
+            None
+        };
+        if let Some(target) = target {
+            if let Ok((_, _, mut interaction, ..)) = buttons.get_mut(target) {
+                *interaction = Interaction::Pressed;
+                focus.pressed = Some(target);
+            }
+        }
+    }
+
     // This system updates the settings when a new value for a setting is selected, and marks
     // the button as the one currently selected
     fn setting_button<T: Resource + Component + PartialEq + Copy>(
@@ -328,6 +491,36 @@ mod menu {
                                     button_text_style.clone(),
                                 ));
                             });
+                        parent
+                            .spawn((
+                                ButtonBundle {
+                                    style: button_style.clone(),
+                                    background_color: NORMAL_BUTTON.into(),
+                                    ..default()
+                                },
+                                MenuButtonAction::Seed,
+                            ))
+                            .with_children(|parent| {
+                                parent.spawn(TextBundle::from_section(
+                                    "Seed",
+                                    button_text_style.clone(),
+                                ));
+                            });
+                        parent
+                            .spawn((
+                                ButtonBundle {
+                                    style: button_style.clone(),
+                                    background_color: NORMAL_BUTTON.into(),
+                                    ..default()
+                                },
+                                MenuButtonAction::Settings,
+                            ))
+                            .with_children(|parent| {
+                                parent.spawn(TextBundle::from_section(
+                                    "Settings",
+                                    button_text_style.clone(),
+                                ));
+                            });
                         
                         
                     });
@@ -379,6 +572,7 @@ mod menu {
                         for (action, text) in [
                             (MenuButtonAction::SettingsDisplay, "Display"),
                             (MenuButtonAction::SettingsSound, "Sound"),
+                            (MenuButtonAction::SettingsControls, "Controls"),
                             (MenuButtonAction::BackToMainMenu, "Back"),
                         ] {
                             parent
@@ -594,6 +788,292 @@ mod menu {
             });
     }
 
+    fn controls_menu_setup(
+        mut commands: Commands,
+        input_map: Res<InputMap>,
+        mut rebinding: ResMut<Rebinding>,
+    ) {
+        rebinding.0 = None;
+        let button_style = Style {
+            width: Val::Px(200.0),
+            height: Val::Px(65.0),
+            margin: UiRect::all(Val::Px(20.0)),
+            justify_content: JustifyContent::Center,
+            align_items: AlignItems::Center,
+            ..default()
+        };
+        let button_text_style = TextStyle {
+            font_size: 40.0,
+            color: TEXT_COLOR,
+            ..default()
+        };
+        // The actions don't all fit in one column of full-sized buttons
+        let binding_text_style = TextStyle {
+            font_size: 24.0,
+            color: TEXT_COLOR,
+            ..default()
+        };
+
+        commands
+            .spawn((
+                NodeBundle {
+                    style: Style {
+                        width: Val::Percent(100.0),
+                        height: Val::Percent(100.0),
+                        align_items: AlignItems::Center,
+                        justify_content: JustifyContent::Center,
+                        ..default()
+                    },
+                    ..default()
+                },
+                OnControlsMenuScreen,
+            ))
+            .with_children(|parent| {
+                parent
+                    .spawn(NodeBundle {
+                        style: Style {
+                            flex_direction: FlexDirection::Column,
+                            align_items: AlignItems::Center,
+                            ..default()
+                        },
+                        background_color: Color::CRIMSON.into(),
+                        ..default()
+                    })
+                    .with_children(|parent| {
+                        parent.spawn(
+                            TextBundle::from_section(
+                                "Click an action, then press a key",
+                                binding_text_style.clone(),
+                            )
+                            .with_style(Style {
+                                margin: UiRect::all(Val::Px(10.0)),
+                                ..default()
+                            }),
+                        );
+                        // Two columns of actions side by side
+                        parent
+                            .spawn(NodeBundle {
+                                style: Style {
+                                    align_items: AlignItems::FlexStart,
+                                    ..default()
+                                },
+                                ..default()
+                            })
+                            .with_children(|parent| {
+                                for column in Action::ALL.chunks(Action::ALL.len().div_ceil(2)) {
+                                    parent
+                                        .spawn(NodeBundle {
+                                            style: Style {
+                                                flex_direction: FlexDirection::Column,
+                                                margin: UiRect::horizontal(Val::Px(10.0)),
+                                                ..default()
+                                            },
+                                            ..default()
+                                        })
+                                        .with_children(|parent| {
+                                            for &action in column {
+                                                parent
+                                                    .spawn((
+                                                        ButtonBundle {
+                                                            style: Style {
+                                                                width: Val::Px(320.0),
+                                                                height: Val::Px(36.0),
+                                                                margin: UiRect::all(Val::Px(4.0)),
+                                                                ..button_style.clone()
+                                                            },
+                                                            background_color: NORMAL_BUTTON.into(),
+                                                            ..default()
+                                                        },
+                                                        RebindButton(action),
+                                                    ))
+                                                    .with_children(|parent| {
+                                                        parent.spawn(TextBundle::from_section(
+                                                            binding_label(&input_map, action, false),
+                                                            binding_text_style.clone(),
+                                                        ));
+                                                    });
+                                            }
+                                        });
+                                }
+                            });
+                        for (action, text) in [
+                            (MenuButtonAction::ResetControls, "Reset"),
+                            (MenuButtonAction::BackToSettings, "Back"),
+                        ] {
+                            parent
+                                .spawn((
+                                    ButtonBundle {
+                                        style: button_style.clone(),
+                                        background_color: NORMAL_BUTTON.into(),
+                                        ..default()
+                                    },
+                                    action,
+                                ))
+                                .with_children(|parent| {
+                                    parent.spawn(TextBundle::from_section(
+                                        text,
+                                        button_text_style.clone(),
+                                    ));
+                                });
+                        }
+                    });
+            });
+    }
+
+    fn binding_label(input_map: &InputMap, action: Action, waiting: bool) -> String {
+        if waiting {
+            format!("{}: press a key", action.label())
+        } else {
+            format!("{}: {}", action.label(), input_map.describe(action))
+        }
+    }
+
+    // Waits for a key after a rebind button is clicked, binds and saves it, and keeps every
+    // button's text up to date
+    fn rebind_controls(
+        buttons: Query<(&Interaction, &RebindButton), Changed<Interaction>>,
+        labels: Query<(&RebindButton, &Children)>,
+        mut texts: Query<&mut Text>,
+        input: Res<Input<KeyCode>>,
+        mut input_map: ResMut<InputMap>,
+        mut rebinding: ResMut<Rebinding>,
+    ) {
+        if let Some(action) = rebinding.0 {
+            if let Some(&key) = input.get_just_pressed().find(|&&key| is_bindable(key)) {
+                input_map.bind(action, key);
+                input_map.save();
+                rebinding.0 = None;
+            }
+        }
+        for (interaction, button) in &buttons {
+            if *interaction == Interaction::Pressed {
+                rebinding.0 = Some(button.0);
+            }
+        }
+
+        if !input_map.is_changed() && !rebinding.is_changed() {
+            return;
+        }
+        for (button, children) in &labels {
+            let waiting = rebinding.0 == Some(button.0);
+            for &child in children {
+                if let Ok(mut text) = texts.get_mut(child) {
+                    text.sections[0].value = binding_label(&input_map, button.0, waiting);
+                }
+            }
+        }
+    }
+
+    fn seed_menu_setup(mut commands: Commands, seed: Res<DungeonSeed>) {
+        let button_style = Style {
+            width: Val::Px(250.0),
+            height: Val::Px(65.0),
+            margin: UiRect::all(Val::Px(20.0)),
+            justify_content: JustifyContent::Center,
+            align_items: AlignItems::Center,
+            ..default()
+        };
+        let button_text_style = TextStyle {
+            font_size: 40.0,
+            color: TEXT_COLOR,
+            ..default()
+        };
+
+        commands
+            .spawn((
+                NodeBundle {
+                    style: Style {
+                        width: Val::Percent(100.0),
+                        height: Val::Percent(100.0),
+                        align_items: AlignItems::Center,
+                        justify_content: JustifyContent::Center,
+                        ..default()
+                    },
+                    ..default()
+                },
+                OnSeedMenuScreen,
+            ))
+            .with_children(|parent| {
+                parent
+                    .spawn(NodeBundle {
+                        style: Style {
+                            flex_direction: FlexDirection::Column,
+                            align_items: AlignItems::Center,
+                            ..default()
+                        },
+                        background_color: Color::CRIMSON.into(),
+                        ..default()
+                    })
+                    .with_children(|parent| {
+                        parent.spawn(
+                            TextBundle::from_section(
+                                "Type a seed",
+                                button_text_style.clone(),
+                            )
+                            .with_style(Style {
+                                margin: UiRect::all(Val::Px(20.0)),
+                                ..default()
+                            }),
+                        );
+                        parent.spawn((
+                            TextBundle::from_section(
+                                seed.0.to_string(),
+                                button_text_style.clone(),
+                            ),
+                            SeedText,
+                        ));
+                        for (action, text) in [
+                            (MenuButtonAction::RandomizeSeed, "Random"),
+                            (MenuButtonAction::BackToMainMenu, "Back"),
+                        ] {
+                            parent
+                                .spawn((
+                                    ButtonBundle {
+                                        style: button_style.clone(),
+                                        background_color: NORMAL_BUTTON.into(),
+                                        ..default()
+                                    },
+                                    action,
+                                ))
+                                .with_children(|parent| {
+                                    parent.spawn(TextBundle::from_section(
+                                        text,
+                                        button_text_style.clone(),
+                                    ));
+                                });
+                        }
+                    });
+            });
+    }
+
+    // Edits the dungeon seed from typed digits, and keeps the seed text in sync with it. A seed
+    // that wasn't typed, like a random one, is replaced by the first digit rather than added to
+    fn seed_input(
+        mut characters: EventReader<ReceivedCharacter>,
+        input: Res<Input<KeyCode>>,
+        mut seed: ResMut<DungeonSeed>,
+        mut texts: Query<&mut Text, With<SeedText>>,
+        mut typed: Local<Option<u64>>,
+    ) {
+        for event in characters.read() {
+            if let Some(digit) = event.char.to_digit(10) {
+                let current = if *typed == Some(seed.0) { seed.0 } else { 0 };
+                seed.0 = current.saturating_mul(10).saturating_add(digit as u64);
+                *typed = Some(seed.0);
+            }
+        }
+        if input.just_pressed(KeyCode::Back) {
+            seed.0 /= 10;
+            *typed = Some(seed.0);
+        }
+
+        if seed.is_changed() {
+            for mut text in &mut texts {
+                text.sections[0].value = seed.0.to_string();
+            }
+        }
+    }
+
     fn menu_action(
         interaction_query: Query<
             (&Interaction, &MenuButtonAction),
@@ -602,6 +1082,8 @@ mod menu {
         mut app_exit_events: EventWriter<AppExit>,
         mut menu_state: ResMut<NextState<MenuState>>,
         mut game_state: ResMut<NextState<GameState>>,
+        mut seed: ResMut<DungeonSeed>,
+        mut input_map: ResMut<InputMap>,
     ) {
         for (interaction, menu_button_action) in &interaction_query {
             if *interaction == Interaction::Pressed {
@@ -618,6 +1100,15 @@ mod menu {
                     MenuButtonAction::SettingsSound => {
                         menu_state.set(MenuState::SettingsSound);
                     }
+                    MenuButtonAction::SettingsControls => {
+                        menu_state.set(MenuState::SettingsControls);
+                    }
+                    MenuButtonAction::ResetControls => {
+                        *input_map = InputMap::default();
+                        input_map.save();
+                    }
+                    MenuButtonAction::Seed => menu_state.set(MenuState::Seed),
+                    MenuButtonAction::RandomizeSeed => *seed = DungeonSeed::random(),
                     MenuButtonAction::BackToMainMenu => menu_state.set(MenuState::Main),
                     MenuButtonAction::BackToSettings => {
                         menu_state.set(MenuState::Settings);
@@ -0,0 +1,252 @@
+use bevy::asset::{io::Reader, AssetLoader, AsyncReadExt, LoadContext};
+use bevy::prelude::*;
+use bevy::utils::BoxedFuture;
+
+use crate::cli_arg;
+use crate::drops::DropSpawning;
+use crate::enemy_spawner::EnemySpawning;
+use crate::main_menu::GameState;
+use crate::player::Player;
+use crate::tile_grid::{TileGrid, TileGridWriter};
+use crate::tilemap::TILE_SIZE;
+
+// Characters a map file can be drawn with. Spaces are left empty
+pub const LEGEND: [(char, MapTile); 10] = [
+    ('#', MapTile::Wall),
+    ('O', MapTile::Obstacle),
+    ('P', MapTile::Pit),
+    ('.', MapTile::Floor),
+    ('+', MapTile::Door),
+    ('@', MapTile::PlayerSpawn),
+    ('E', MapTile::EnemySpawn),
+    ('$', MapTile::Drop("coin")),
+    ('h', MapTile::Drop("health")),
+    ('d', MapTile::Drop("damage_up")),
+];
+
+pub struct MapFilePlugin;
+
+impl Plugin for MapFilePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<MapFile>()
+            .init_asset_loader::<MapFileLoader>()
+            .insert_resource(MapFileSource::from_env())
+            .add_systems(OnEnter(GameState::Game), load_map_file)
+            .add_systems(Update, spawn_map_file.run_if(in_state(GameState::Game)));
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MapTile {
+    #[default]
+    Empty,
+    Wall,
+    // Blocks movement like a wall, but sits inside a room
+    Obstacle,
+    // Blocks walking, but not flying
+    Pit,
+    Floor,
+    Door,
+    PlayerSpawn,
+    EnemySpawn,
+    Drop(&'static str),
+}
+
+// A grid of tiles, stored row by row from the top line of the file down
+#[derive(Asset, TypePath, Clone, Debug, Default)]
+pub struct MapFile {
+    pub width: usize,
+    pub height: usize,
+    pub tiles: Vec<MapTile>,
+}
+
+impl MapFile {
+    pub fn parse(text: &str) -> Result<MapFile, MapFileError> {
+        let lines: Vec<&str> = text.lines().map(|line| line.trim_end()).collect();
+        let width = lines
+            .iter()
+            .map(|line| line.chars().count())
+            .max()
+            .unwrap_or(0);
+        let height = lines.len();
+
+        let mut tiles = vec![MapTile::Empty; width * height];
+        for (row, line) in lines.iter().enumerate() {
+            for (column, tile) in line.chars().enumerate() {
+                if tile == ' ' {
+                    continue;
+                }
+                let Some((_, tile)) = LEGEND.iter().find(|(c, _)| *c == tile) else {
+                    return Err(MapFileError::UnknownTile {
+                        tile,
+                        line: row + 1,
+                        column: column + 1,
+                    });
+                };
+                tiles[row * width + column] = *tile;
+            }
+        }
+
+        Ok(MapFile {
+            width,
+            height,
+            tiles,
+        })
+    }
+
+    pub fn get(&self, column: usize, row: usize) -> MapTile {
+        self.tiles[row * self.width + column]
+    }
+
+    // World position of the centre of a tile, with the whole map centred on `origin`
+    pub fn tile_position(&self, column: usize, row: usize, origin: Vec2) -> Vec2 {
+        let size = Vec2::new(self.width as f32, self.height as f32) * TILE_SIZE;
+        origin - size / 2.
+            + Vec2::new(column as f32 + 0.5, (self.height - 1 - row) as f32 + 0.5) * TILE_SIZE
+    }
+}
+
+#[derive(Debug)]
+pub enum MapFileError {
+    Io(std::io::Error),
+    UnknownTile {
+        tile: char,
+        line: usize,
+        column: usize,
+    },
+}
+
+impl std::fmt::Display for MapFileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MapFileError::Io(err) => write!(f, "could not read map file: {}", err),
+            MapFileError::UnknownTile { tile, line, column } => write!(
+                f,
+                "unknown map tile {:?} at line {}, column {}",
+                tile, line, column
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MapFileError {}
+
+impl From<std::io::Error> for MapFileError {
+    fn from(err: std::io::Error) -> Self {
+        MapFileError::Io(err)
+    }
+}
+
+#[derive(Default)]
+pub struct MapFileLoader;
+
+impl AssetLoader for MapFileLoader {
+    type Asset = MapFile;
+    type Settings = ();
+    type Error = MapFileError;
+
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        _settings: &'a (),
+        _load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<MapFile, MapFileError>> {
+        Box::pin(async move {
+            let mut text = String::new();
+            reader.read_to_string(&mut text).await?;
+            MapFile::parse(&text)
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        // Not plain `txt`, so other text files under `assets/` aren't read as maps
+        &["map.txt"]
+    }
+}
+
+// A hand-authored map to play instead of a generated dungeon, picked with `--map <path>` or the
+// `DUNGEON_MAP` environment variable. Paths are relative to `assets/`, e.g.
+// `--map dungeon.map.txt`
+#[derive(Resource, Default)]
+pub struct MapFileSource {
+    pub path: Option<String>,
+    handle: Option<Handle<MapFile>>,
+}
+
+impl MapFileSource {
+    pub fn from_env() -> Self {
+        MapFileSource {
+            path: cli_arg("map").or_else(|| std::env::var("DUNGEON_MAP").ok()),
+            handle: None,
+        }
+    }
+}
+
+pub fn no_map_file(source: Res<MapFileSource>) -> bool {
+    source.path.is_none()
+}
+
+fn load_map_file(mut source: ResMut<MapFileSource>, asset_server: Res<AssetServer>) {
+    if let Some(path) = source.path.clone() {
+        source.handle = Some(asset_server.load(path));
+    }
+}
+
+fn spawn_map_file(
+    mut commands: Commands,
+    mut source: ResMut<MapFileSource>,
+    maps: Res<Assets<MapFile>>,
+    mut grid_writer: TileGridWriter,
+    mut players: Query<&mut Transform, With<Player>>,
+    mut enemies: EnemySpawning,
+    mut drops: DropSpawning,
+) {
+    let Some(map) = source.handle.as_ref().and_then(|handle| maps.get(handle)) else {
+        return;
+    };
+
+    // Row 0 is the top line of the file, but the bottom row of the grid
+    let mut grid = TileGrid::new(
+        map.tile_position(0, map.height.saturating_sub(1), Vec2::ZERO),
+        map.width,
+        map.height,
+    );
+
+    for row in 0..map.height {
+        for column in 0..map.width {
+            let tile = map.get(column, row);
+            let position = map.tile_position(column, row, Vec2::ZERO);
+            let cell = grid.world_to_grid(position);
+            grid.set(cell, tile.into());
+
+            // Tiles are drawn from the grid by `TileMapPlugin` and `WallPlugin`
+            match tile {
+                MapTile::PlayerSpawn => {
+                    for mut player_transform in &mut players {
+                        player_transform.translation.x = position.x;
+                        player_transform.translation.y = position.y;
+                    }
+                }
+                MapTile::EnemySpawn => {
+                    // `drops` holds the dungeon's rngs, enemies roll on the spawn one
+                    let rng = &mut drops.rng.spawns;
+                    enemies.spawn_enemy(&mut commands, rng, position.extend(0.), None);
+                }
+                MapTile::Drop(drop) => {
+                    let transform = Transform::from_translation(position.extend(-1.));
+                    drops.spawn(&mut commands, drop, transform);
+                }
+                MapTile::Empty
+                | MapTile::Floor
+                | MapTile::Wall
+                | MapTile::Obstacle
+                | MapTile::Pit
+                | MapTile::Door => {}
+            }
+        }
+    }
+    grid_writer.set(grid);
+
+    // Only spawn the map once
+    source.handle = None;
+}
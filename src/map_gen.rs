@@ -1,29 +1,48 @@
 use bevy::{
-    prelude::*, render::render_resource::encase::ArrayLength, sprite::MaterialMesh2dBundle,
+    asset::LoadedFolder, ecs::system::SystemParam, prelude::*,
+    render::render_resource::encase::ArrayLength, sprite::MaterialMesh2dBundle,
 };
+use rand::rngs::StdRng;
 use rand::Rng;
-pub const MIN_LEAF_SIZE: f32 = 200.;
-pub const MAX_LEAF_SIZE: f32 = 600.;
-use crate::tilemap::{TileCollider, TILE_SIZE};
+
+use crate::camera::GameCamera;
+use crate::drops::{random_reward, spawn_drop, DropsParent, ShopItem};
+use crate::floor::DungeonFloor;
+use crate::layout::{DungeonLayout, Leaf, RoomKind};
+use crate::main_menu::GameState;
+use crate::map_file::{no_map_file, MapFile, MapTile};
+use crate::map_generator::MapGeneratorConfig;
+use crate::room_templates::{
+    apply_room_templates, load_room_templates, room_templates_loaded, RoomTemplates,
+};
+use crate::player::Player;
+use crate::seed::{DungeonRng, DungeonSeed, MAP_STREAM};
+use crate::tile_grid::{TileGrid, TileGridWriter};
 
 pub struct MapGenPlugin;
 impl Plugin for MapGenPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Startup, start_level)
+        app.insert_resource(MapGeneratorConfig::from_env())
+            .add_systems(Startup, load_room_templates)
+            .add_systems(
+                Update,
+                (
+                    // Runs once the room templates are in, then again whenever the floor changes.
+                    // Checking the templates first keeps the floor change from being missed
+                    start_level.run_if(
+                        room_templates_loaded
+                            .and_then(resource_changed::<DungeonFloor>())
+                            .and_then(no_map_file),
+                    ),
+                    place_player,
+                    furnish_rooms,
+                )
+                    .run_if(in_state(GameState::Game)),
+            )
             .register_type::<Leaf>();
     }
 }
 
-#[derive(Component, Default, Reflect)]
-#[reflect(Component)]
-pub struct Leaf {
-    x: f32,
-    y: f32,
-    width: f32,
-    height: f32,
-    child_split: bool,
-}
-
 #[derive(Component)]
 pub struct RoomTag {
     pub width: f32,
@@ -33,78 +52,55 @@ pub struct RoomTag {
     pub is_top_edge: bool,
     pub is_bottom_edge: bool,
     pub doors: Vec<Vec2>,
+    // Set once the room's encounter has been beaten, so it stays open on later visits
+    pub cleared: bool,
+    pub kind: RoomKind,
+    // Obstacles, pits, enemy spawn points and drops from the room's template
+    pub features: Vec<(Vec2, MapTile)>,
 }
 
-impl Leaf {
-    fn split(&mut self) -> Option<(Leaf, Leaf)> {
-        let mut rng = rand::thread_rng();
+// A corridor between two rooms. Its floor is drawn by its children
+#[derive(Component)]
+pub struct CorridorTag;
 
-        if self.child_split {
-            return None;
-        }
+// One stretch of a corridor's floor. It shows up on the minimap once the player has walked it
+#[derive(Component)]
+pub struct CorridorFloor {
+    pub rect: Rect,
+    pub explored: bool,
+}
 
-        let max: f32;
-        let mut split_b: bool = rng.gen_bool(0.5);
-        if (self.width > self.height) && (self.width / self.height >= 1.25) {
-            max = self.width - MIN_LEAF_SIZE;
-            split_b = false;
-        } else if (self.height > self.width) && (self.height / self.width >= 1.25) {
-            max = self.height - MIN_LEAF_SIZE;
-            split_b = true;
-        } else {
-            max = if split_b {
-                self.height - MIN_LEAF_SIZE
-            } else {
-                self.width - MIN_LEAF_SIZE
-            }
-        }
-        if max <= MIN_LEAF_SIZE {
-            return None;
-        }
+impl RoomTag {
+    pub fn rect(&self, translation: Vec3) -> Rect {
+        Rect::from_center_size(translation.xy(), Vec2::new(self.width, self.height))
+    }
+}
 
-        let split = rng.gen_range(MIN_LEAF_SIZE..max);
-        let left_child;
-        let right_child;
-        /*
-        divide by height
-        */
-        if split_b {
-            left_child = Leaf {
-                x: self.x,
-                y: self.y,
-                width: self.width,
-                height: split,
-                child_split: false,
-            };
-            right_child = Leaf {
-                x: self.x,
-                y: self.y + split,
-                width: self.width,
-                height: self.height - split,
-                child_split: false,
-            };
+// Everything that decides how the current floor is laid out
+#[derive(SystemParam)]
+struct FloorPlan<'w> {
+    seed: Res<'w, DungeonSeed>,
+    floor: Res<'w, DungeonFloor>,
+    generators: Res<'w, MapGeneratorConfig>,
+    templates: Res<'w, RoomTemplates>,
+    folders: Res<'w, Assets<LoadedFolder>>,
+    maps: Res<'w, Assets<MapFile>>,
+}
 
-        /*
-        divide by width
-        */
-        } else {
-            left_child = Leaf {
-                x: self.x,
-                y: self.y,
-                width: split,
-                height: self.height,
-                child_split: false,
-            };
-            right_child = Leaf {
-                x: self.x + split,
-                y: self.y,
-                width: self.width - split,
-                height: self.height,
-                child_split: false,
-            };
+impl FloorPlan<'_> {
+    // Also hands back the floor's rng, so whatever gets rolled after the layout stays seeded
+    fn generate(&self) -> (DungeonLayout, StdRng) {
+        let floor = self.floor.0;
+        let mut rng = self.seed.floor_rng(MAP_STREAM, floor);
+        let kind = self.generators.kind_for_floor(floor);
+        info!("Generating floor {} with {:?}", floor, kind);
+        let mut layout = kind.generator().generate(&mut rng);
+        if !layout.is_connected() {
+            warn!("Floor {} has rooms that can't be reached from the others", floor);
         }
-        self.child_split = true;
-        return Some((left_child, right_child));
+        let templates = self.templates.loaded(&self.folders, &self.maps);
+        apply_room_templates(&mut layout, &templates, &mut rng);
+        (layout, rng)
     }
 }
 
@@ -112,256 +108,168 @@ fn start_level(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<ColorMaterial>>,
+    plan: FloorPlan,
+    mut grid_writer: TileGridWriter,
 ) {
-    let mut _leaf: Vec<Leaf> = vec![];
-    let weight = 2.;
-    let root = Leaf {
-        x: -640. * weight,
-        y: -360. * weight,
-        width: 1280. * weight,
-        height: 720. * weight,
-        child_split: false,
-    };
-    _leaf.push(root);
-    let mut fully_split = false;
-    while !fully_split {
-        fully_split = true;
-        let mut temp_leaf: Vec<Leaf> = vec![];
-        for l in _leaf.iter_mut() {
-            if l.child_split {
-                continue;
-            }
-            if l.width > MAX_LEAF_SIZE || l.height > MAX_LEAF_SIZE {
-                let leaves = l.split();
-                match leaves {
-                    Some(leaf) => {
-                        temp_leaf.push(leaf.0);
-                        temp_leaf.push(leaf.1);
-                        fully_split = false;
-                    }
-                    None => println!("Not a thing"),
-                }
-            }
-        }
-        _leaf.append(&mut temp_leaf)
+    let (layout, mut rng) = plan.generate();
+    grid_writer.set(TileGrid::from_layout(&layout));
+
+    for room in layout.rooms.iter() {
+        let size = room.rect.size();
+        let center = room.rect.center();
+        let spawn_transform = Transform {
+            translation: Vec3::new(center.x, center.y, -10.),
+            ..Default::default()
+        };
+
+        commands.spawn((
+            MaterialMesh2dBundle {
+                mesh: meshes.add(shape::Quad::new(size).into()).into(),
+                material: materials.add(ColorMaterial::from(room_color(room.kind, &mut rng))),
+                transform: spawn_transform,
+                ..default()
+            },
+            RoomTag {
+                width: size.x,
+                height: size.y,
+                is_left_edge: room.is_left_edge,
+                is_right_edge: room.is_right_edge,
+                is_top_edge: room.is_top_edge,
+                is_bottom_edge: room.is_bottom_edge,
+                doors: room.doors.clone(),
+                // Only normal and boss rooms have a fight in them
+                cleared: !matches!(room.kind, RoomKind::Normal | RoomKind::Boss),
+                kind: room.kind,
+                features: room.features.clone(),
+            },
+            Name::new(format!("{:?} Room", room.kind)),
+        ));
     }
 
-    let mut rng = rand::thread_rng();
-
-    // Inside your main loop or function where you iterate over leaves
-    for (i, l) in _leaf.iter().enumerate() {
-        if !l.child_split {
-            let room_min = Vec2::new(l.x, l.y);
-            let room_max = Vec2::new(l.x + l.width, l.y + l.height);
-            let is_left_edge = room_min.x == l.x;
-            let is_right_edge = room_max.x == -l.x;
-            let is_bottom_edge = room_min.y == l.y;
-            let is_top_edge = room_max.y == -l.y;
-            /*             println!("Room Min: {}", room_min);
-            println!("Room Max: {}", room_max);
-            println!(); */
-            let spawn_transform = Transform {
-                translation: Vec3::new(l.x + l.width / 2., l.y + l.height / 2., -10.),
-                ..Default::default()
-            };
-            let mut doors = Vec::new();
-
-            // Check if this leaf shares an edge with any other leaf
-            for other_leaf in _leaf.iter().skip(i + 1) {
-                // Check if the two leaves share an edge and calculate door positions if needed
-                doors.extend(calculate_door_positions(&l, &other_leaf));
-            }
-
-            commands.spawn((
-                MaterialMesh2dBundle {
-                    mesh: meshes
-                        .add(shape::Quad::new(Vec2::new(l.width, l.height)).into())
-                        .into(),
-                    material: materials.add(ColorMaterial::from(Color::rgb(
-                        rng.gen_range(0.0..1.0),
-                        rng.gen_range(0.0..1.0),
-                        rng.gen_range(0.0..1.0),
-                    ))),
-                    transform: spawn_transform,
-                    ..default()
-                },
-                RoomTag {
-                    width: l.width,
-                    height: l.height,
-                    is_left_edge,
-                    is_right_edge,
-                    is_top_edge,
-                    is_bottom_edge,
-                    doors, // Add the calculated doors to the RoomTag
-                },
-            ));
-        }
+    let corridor_material = materials.add(ColorMaterial::from(Color::rgb(0.35, 0.3, 0.25)));
+    for corridor in layout.corridors.iter() {
+        commands
+            .spawn((
+                SpatialBundle::default(),
+                CorridorTag,
+                Name::new("Corridor"),
+            ))
+            .with_children(|commands| {
+                for floor in &corridor.floor {
+                    commands.spawn((
+                        MaterialMesh2dBundle {
+                            mesh: meshes.add(shape::Quad::new(floor.size()).into()).into(),
+                            material: corridor_material.clone(),
+                            transform: Transform::from_translation(floor.center().extend(-10.)),
+                            ..default()
+                        },
+                        CorridorFloor {
+                            rect: *floor,
+                            explored: false,
+                        },
+                    ));
+                }
+            });
     }
 }
 
-// Define a function to calculate door positions for leaves sharing the same edge
-fn calculate_door_positions(leaf: &Leaf, other_leaf: &Leaf) -> Vec<Vec2> {
-    let mut doors = Vec::new();
-    // Calculate the boundaries of both leaves
-    let leaf_left_x = leaf.x;
-    let leaf_right_x = leaf.x + leaf.width;
-    let other_leaf_left_x = other_leaf.x;
-    let other_leaf_right_x = other_leaf.x + other_leaf.width;
-
-    let leaf_bottom_y = leaf.y;
-    let leaf_top_y = leaf.y + leaf.height;
-    let other_leaf_bottom_y = other_leaf.y;
-    let other_leaf_top_y = other_leaf.y + leaf.height;
-
-// Tolerance for overlap
-let tolerance = 1.0;
-
-// Check for overlap and calculate door position if the rooms share the top edge
-if leaf_top_y >= other_leaf_bottom_y - tolerance
-    && leaf_top_y <= other_leaf_top_y + tolerance
-    && (leaf_left_x..=leaf_right_x).contains(&other_leaf_left_x)
-{
-    let door_position = Vec2::new((leaf_right_x + other_leaf_left_x) / 2.0, leaf_top_y);
-    doors.push(door_position);
-}
-
-// Check for overlap and calculate door position if the rooms share the right edge
-if leaf_right_x >= other_leaf_left_x - tolerance
-    && leaf_right_x <= other_leaf_right_x + tolerance
-    && (leaf_bottom_y..=leaf_top_y).contains(&other_leaf_bottom_y)
-{
-    let door_position = Vec2::new(leaf_right_x, (leaf_top_y + other_leaf_bottom_y) / 2.0);
-    doors.push(door_position);
-}
-
-// Check for overlap and calculate door position if the rooms share the bottom edge
-if leaf_bottom_y >= other_leaf_top_y - tolerance
-    && leaf_bottom_y <= other_leaf_bottom_y + tolerance
-    && (leaf_left_x..=leaf_right_x).contains(&other_leaf_left_x)
-{
-    let door_position = Vec2::new((leaf_right_x + other_leaf_left_x) / 2.0, leaf_bottom_y);
-    doors.push(door_position);
-}
-
-// Check for overlap and calculate door position if the rooms share the left edge
-if leaf_left_x >= other_leaf_right_x - tolerance
-    && leaf_left_x <= other_leaf_left_x + tolerance
-    && (leaf_bottom_y..=leaf_top_y).contains(&other_leaf_bottom_y)
-{
-    let door_position = Vec2::new(leaf_left_x, (leaf_top_y + other_leaf_bottom_y) / 2.0);
-    doors.push(door_position);
-}
-
-
-    // Repeat similar checks for other edges if needed
-    doors
+fn room_color(kind: RoomKind, rng: &mut impl Rng) -> Color {
+    match kind {
+        RoomKind::Normal => Color::rgb(
+            rng.gen_range(0.0..1.0),
+            rng.gen_range(0.0..1.0),
+            rng.gen_range(0.0..1.0),
+        ),
+        RoomKind::Start => Color::rgb(0.6, 0.6, 0.6),
+        RoomKind::Boss => Color::rgb(0.5, 0.05, 0.05),
+        RoomKind::Shop => Color::rgb(0.85, 0.7, 0.2),
+        RoomKind::Treasure => Color::rgb(0.2, 0.5, 0.9),
+    }
 }
 
-
-fn create_walls_and_doorway(
-    commands: &mut Commands,
-    assets: &Res<AssetServer>,
-    width: f32,
-    height: f32,
-    room_location: Vec3,
-    door_locations: &Vec<Vec2>,
+fn place_player(
+    rooms: Query<(&Transform, &RoomTag), Added<RoomTag>>,
+    mut players: Query<&mut Transform, (With<Player>, Without<RoomTag>)>,
+    mut cameras: Query<&mut GameCamera>,
 ) {
-    let room_size = Vec2::new(width, height);
-    let room_min = room_location.xy() - room_size / 2.0;
-    let room_max = room_location.xy() + room_size / 2.0;
-    let mut x_wall = room_min.x;
-    let mut y_wall = room_min.y;
-
-    while x_wall < room_max.x - TILE_SIZE {
-        let mut skip_wall = false;
-        for door_pos in door_locations.iter() {
-            if (x_wall + TILE_SIZE > door_pos.x) && (x_wall < door_pos.x + TILE_SIZE) {
-                skip_wall = true;
-                break;
-            }
+    for (room_transform, room) in &rooms {
+        if room.kind != RoomKind::Start {
+            continue;
         }
-
-        if skip_wall {
-            x_wall += TILE_SIZE;
-            continue; // Skip spawning wall segments under the doorway
+        for mut player_transform in &mut players {
+            player_transform.translation.x = room_transform.translation.x;
+            player_transform.translation.y = room_transform.translation.y;
         }
+        // Straight there, rather than panning across the whole floor
+        for mut camera in &mut cameras {
+            camera.focus = room_transform.translation.xy();
+        }
+    }
+}
 
-        let texture = assets.load("wall.png");
-        commands.spawn(SpriteBundle {
-            texture,
-            transform: Transform {
-                translation: Vec3::new(x_wall, room_max.y, 10.0),
-                scale: Vec3::splat(1.),
-                ..Default::default()
-            },
-            ..Default::default()
-        });
-
-        let texture = assets.load("wall.png");
-        commands.spawn(SpriteBundle {
-            texture,
-            transform: Transform {
-                translation: Vec3::new(x_wall, room_min.y, 10.0),
-                scale: Vec3::splat(1.),
-                ..Default::default()
-            },
-            ..Default::default()
-        });
+// Shop prices, laid out left to right across the middle of the shop
+const SHOP_STOCK: [(&str, f32); 3] = [("health", 15.), ("damage_up", 30.), ("health", 15.)];
 
-        x_wall += TILE_SIZE;
-    }
+fn furnish_rooms(
+    mut commands: Commands,
+    rooms: Query<(&Transform, &RoomTag), Added<RoomTag>>,
+    drops_parent: Query<Entity, With<DropsParent>>,
+    asset_server: Res<AssetServer>,
+    mut dungeon_rng: ResMut<DungeonRng>,
+) {
+    let Ok(drops_parent) = drops_parent.get_single() else {
+        return;
+    };
 
-    while y_wall < room_max.y {
-        let mut skip_wall = false;
-        for door_pos in door_locations.iter() {
-            if (y_wall + TILE_SIZE > door_pos.y) && (y_wall < door_pos.y + TILE_SIZE) {
-                skip_wall = true;
-                break;
+    for (room_transform, room) in &rooms {
+        let mut transform = *room_transform;
+        transform.translation.z = -1.0;
+
+        match room.kind {
+            RoomKind::Shop => {
+                let mut items = Vec::new();
+                commands.entity(drops_parent).with_children(|commands| {
+                    for (i, (drop, _)) in SHOP_STOCK.iter().enumerate() {
+                        let mut transform = transform;
+                        transform.translation.x += (i as f32 - 1.) * 24.;
+                        items.push(spawn_drop(commands, &asset_server, drop, transform));
+                    }
+                });
+                for (item, (_, price)) in items.into_iter().zip(SHOP_STOCK) {
+                    commands
+                        .entity(item)
+                        .insert(ShopItem { price })
+                        .with_children(|commands| {
+                            commands.spawn(Text2dBundle {
+                                text: Text::from_section(
+                                    format!("${}", price),
+                                    TextStyle {
+                                        font_size: 8.0,
+                                        ..default()
+                                    },
+                                ),
+                                transform: Transform::from_xyz(0., -10., 0.),
+                                ..default()
+                            });
+                        });
+                }
             }
+            RoomKind::Treasure => {
+                let drop = random_reward(&mut dungeon_rng.loot);
+                commands.entity(drops_parent).with_children(|commands| {
+                    spawn_drop(commands, &asset_server, drop, transform);
+                });
+            }
+            _ => {}
         }
 
-        if skip_wall {
-            y_wall += TILE_SIZE;
-            continue; // Skip spawning wall segments under the doorway
-        }
-
-        let texture = assets.load("wall.png");
-        commands.spawn(SpriteBundle {
-            texture,
-            transform: Transform {
-                translation: Vec3::new(room_max.x, y_wall, 10.0),
-                scale: Vec3::splat(1.),
-                ..Default::default()
-            },
-            ..Default::default()
-        });
-
-        let texture = assets.load("wall.png");
-        commands.spawn(SpriteBundle {
-            texture,
-            transform: Transform {
-                translation: Vec3::new(room_min.x, y_wall, 10.0),
-                scale: Vec3::splat(1.),
-                ..Default::default()
-            },
-            ..Default::default()
-        });
-
-        y_wall += TILE_SIZE;
-    }
-
-    // Spawn the doorway sprite
-    for door_location in door_locations {
-        let doorway_texture = assets.load("sand.png");
-
-        commands.spawn(SpriteBundle {
-            texture: doorway_texture,
-            transform: Transform {
-                translation:  Vec3::new(door_location.x,door_location.y,10.),
-                scale: Vec3::splat(1.),
-                ..Default::default()
-            },
-            ..Default::default()
+        commands.entity(drops_parent).with_children(|commands| {
+            for (position, feature) in &room.features {
+                if let MapTile::Drop(drop) = feature {
+                    let transform = Transform::from_translation(position.extend(-1.0));
+                    spawn_drop(commands, &asset_server, drop, transform);
+                }
+            }
         });
     }
 }
@@ -0,0 +1,438 @@
+use bevy::prelude::*;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+use crate::cli_arg;
+use crate::layout::{default_bounds, DungeonLayout, DungeonLayoutBuilder, Leaf, Room, RoomKind};
+use crate::map_file::MapTile;
+use crate::tile_grid::{TileGrid, TileKind};
+use crate::tilemap::TILE_SIZE;
+
+// Cave maps are cut into sectors of roughly this size, which act as their rooms
+const SECTOR_SIZE: f32 = 400.;
+// Open space, in cells, kept around the middle of every sector so the player, boss, shop and
+// stairs always have somewhere to stand
+const CLEARING_RADIUS: i32 = 5;
+// Half width, in cells, of the tunnels joining neighbouring sectors
+const TUNNEL_RADIUS: i32 = 1;
+const ENEMY_SPAWN_POINTS: usize = 4;
+
+// Something that can lay out a dungeon. Everything downstream of `map_gen::start_level` only
+// sees the `DungeonLayout`, so generators can be swapped without touching any spawning code
+pub trait MapGenerator {
+    fn generate(&self, rng: &mut StdRng) -> DungeonLayout;
+}
+
+impl MapGenerator for DungeonLayoutBuilder {
+    fn generate(&self, rng: &mut StdRng) -> DungeonLayout {
+        self.build(rng)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MapGeneratorKind {
+    // Rectangular rooms from a BSP tree
+    Bsp,
+    // BSP rooms shrunk inside their leaves and joined by corridors
+    Corridors,
+    // Cellular automata caves
+    Caves,
+    // Winding tunnels dug by a random walk
+    Drunkard,
+}
+
+impl MapGeneratorKind {
+    // Generators cycle through this list floor by floor unless one is picked in config
+    const ROTATION: [MapGeneratorKind; 4] = [
+        MapGeneratorKind::Bsp,
+        MapGeneratorKind::Caves,
+        MapGeneratorKind::Corridors,
+        MapGeneratorKind::Drunkard,
+    ];
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "bsp" => Some(MapGeneratorKind::Bsp),
+            "corridors" => Some(MapGeneratorKind::Corridors),
+            "caves" => Some(MapGeneratorKind::Caves),
+            "drunkard" => Some(MapGeneratorKind::Drunkard),
+            _ => None,
+        }
+    }
+
+    pub fn generator(self) -> Box<dyn MapGenerator> {
+        match self {
+            MapGeneratorKind::Bsp => Box::new(DungeonLayout::builder()),
+            MapGeneratorKind::Corridors => Box::new(DungeonLayout::builder().corridors(true)),
+            MapGeneratorKind::Caves => Box::new(CaveGenerator::default()),
+            MapGeneratorKind::Drunkard => Box::new(DrunkardGenerator::default()),
+        }
+    }
+}
+
+// Which generator each floor uses. `--generator <bsp|corridors|caves|drunkard>` or the `DUNGEON_GENERATOR`
+// environment variable pins every floor to one generator
+#[derive(Resource, Default)]
+pub struct MapGeneratorConfig {
+    pub fixed: Option<MapGeneratorKind>,
+}
+
+impl MapGeneratorConfig {
+    pub fn from_env() -> Self {
+        let name = cli_arg("generator").or_else(|| std::env::var("DUNGEON_GENERATOR").ok());
+        let fixed = name.and_then(|name| {
+            let kind = MapGeneratorKind::parse(&name);
+            if kind.is_none() {
+                warn!(
+                    "Unknown map generator {:?}, rotating generators instead",
+                    name
+                );
+            }
+            kind
+        });
+        MapGeneratorConfig { fixed }
+    }
+
+    pub fn kind_for_floor(&self, floor: u32) -> MapGeneratorKind {
+        self.fixed.unwrap_or_else(|| {
+            let rotation = &MapGeneratorKind::ROTATION;
+            rotation[floor.saturating_sub(1) as usize % rotation.len()]
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct CaveGenerator {
+    pub bounds: Rect,
+    // Chance for each cell to start out as rock
+    pub fill: f64,
+    pub smoothing_steps: usize,
+    pub treasure_rooms: usize,
+}
+
+impl Default for CaveGenerator {
+    fn default() -> Self {
+        CaveGenerator {
+            bounds: default_bounds(),
+            fill: 0.45,
+            smoothing_steps: 4,
+            treasure_rooms: 2,
+        }
+    }
+}
+
+impl MapGenerator for CaveGenerator {
+    fn generate(&self, rng: &mut StdRng) -> DungeonLayout {
+        let mut grid = CellGrid::new(self.bounds);
+        for y in 0..grid.height as i32 {
+            for x in 0..grid.width as i32 {
+                grid.set(x, y, !rng.gen_bool(self.fill));
+            }
+        }
+
+        // Each step rock stays rock with at least 4 rock neighbours and open cells fill in with 5,
+        // which clumps the noise into caves
+        for _ in 0..self.smoothing_steps {
+            let mut next = grid.clone();
+            for y in 0..grid.height as i32 {
+                for x in 0..grid.width as i32 {
+                    let needed = if grid.is_open(x, y) { 5 } else { 4 };
+                    next.set(x, y, grid.rock_around(x, y) < needed);
+                }
+            }
+            grid = next;
+        }
+
+        grid.into_layout(rng, self.treasure_rooms)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct DrunkardGenerator {
+    pub bounds: Rect,
+    // Share of the map dug out before the walk stops
+    pub open_fraction: f32,
+    // Chance each step that the walker gives up on its tunnel and starts again from somewhere
+    // already dug
+    pub restart_chance: f64,
+    pub treasure_rooms: usize,
+}
+
+impl Default for DrunkardGenerator {
+    fn default() -> Self {
+        DrunkardGenerator {
+            bounds: default_bounds(),
+            open_fraction: 0.35,
+            restart_chance: 0.005,
+            treasure_rooms: 2,
+        }
+    }
+}
+
+impl MapGenerator for DrunkardGenerator {
+    fn generate(&self, rng: &mut StdRng) -> DungeonLayout {
+        let mut grid = CellGrid::new(self.bounds);
+        let target = (grid.width * grid.height) as f32 * self.open_fraction;
+        let steps = grid.width * grid.height * 4;
+
+        let mut walker = IVec2::new(grid.width as i32 / 2, grid.height as i32 / 2);
+        let mut dug = Vec::new();
+        for _ in 0..steps {
+            if dug.len() as f32 >= target {
+                break;
+            }
+            for cell in grid.carve(walker, TUNNEL_RADIUS) {
+                dug.push(cell);
+            }
+
+            if rng.gen_bool(self.restart_chance) {
+                walker = *dug.choose(rng).unwrap();
+                continue;
+            }
+            let step = *[IVec2::X, IVec2::NEG_X, IVec2::Y, IVec2::NEG_Y]
+                .choose(rng)
+                .unwrap();
+            walker = (walker + step).clamp(
+                IVec2::splat(TUNNEL_RADIUS + 1),
+                IVec2::new(grid.width as i32, grid.height as i32) - TUNNEL_RADIUS - 2,
+            );
+        }
+
+        grid.into_layout(rng, self.treasure_rooms)
+    }
+}
+
+// Open and solid cells covering the map, one per tile, from the bottom left corner
+#[derive(Clone, Debug)]
+struct CellGrid {
+    origin: Vec2,
+    width: usize,
+    height: usize,
+    open: Vec<bool>,
+}
+
+impl CellGrid {
+    fn new(bounds: Rect) -> Self {
+        let width = (bounds.width() / TILE_SIZE) as usize;
+        let height = (bounds.height() / TILE_SIZE) as usize;
+        CellGrid {
+            origin: bounds.min,
+            width,
+            height,
+            open: vec![false; width * height],
+        }
+    }
+
+    fn contains(&self, x: i32, y: i32) -> bool {
+        x >= 0 && y >= 0 && (x as usize) < self.width && (y as usize) < self.height
+    }
+
+    // The outermost ring of cells is always rock, so the map is closed off
+    fn is_border(&self, x: i32, y: i32) -> bool {
+        x <= 0 || y <= 0 || x as usize >= self.width - 1 || y as usize >= self.height - 1
+    }
+
+    fn is_open(&self, x: i32, y: i32) -> bool {
+        self.contains(x, y) && self.open[y as usize * self.width + x as usize]
+    }
+
+    fn set(&mut self, x: i32, y: i32, open: bool) {
+        if self.contains(x, y) {
+            self.open[y as usize * self.width + x as usize] = open && !self.is_border(x, y);
+        }
+    }
+
+    // Rock cells among the 8 around a cell, counting off the map as rock
+    fn rock_around(&self, x: i32, y: i32) -> usize {
+        let mut count = 0;
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                if (dx != 0 || dy != 0) && !self.is_open(x + dx, y + dy) {
+                    count += 1;
+                }
+            }
+        }
+        count
+    }
+
+    fn center_of(&self, x: i32, y: i32) -> Vec2 {
+        self.origin + (Vec2::new(x as f32, y as f32) + 0.5) * TILE_SIZE
+    }
+
+    // Opens a square of cells, returning the ones that were rock before
+    fn carve(&mut self, center: IVec2, radius: i32) -> Vec<IVec2> {
+        let mut carved = Vec::new();
+        for y in center.y - radius..=center.y + radius {
+            for x in center.x - radius..=center.x + radius {
+                if self.contains(x, y) && !self.is_border(x, y) && !self.is_open(x, y) {
+                    self.set(x, y, true);
+                    carved.push(IVec2::new(x, y));
+                }
+            }
+        }
+        carved
+    }
+
+    fn carve_circle(&mut self, center: IVec2, radius: i32) {
+        for y in center.y - radius..=center.y + radius {
+            for x in center.x - radius..=center.x + radius {
+                if (IVec2::new(x, y) - center).length_squared() <= radius * radius {
+                    self.set(x, y, true);
+                }
+            }
+        }
+    }
+
+    // Digs along x, then along y
+    fn carve_tunnel(&mut self, from: IVec2, to: IVec2) {
+        let mut cell = from;
+        while cell != to {
+            self.carve(cell, TUNNEL_RADIUS);
+            if cell.x != to.x {
+                cell.x += (to.x - cell.x).signum();
+            } else {
+                cell.y += (to.y - cell.y).signum();
+            }
+        }
+        self.carve(to, TUNNEL_RADIUS);
+    }
+
+    // Fills in every pocket of open cells that can't be walked to from `start`
+    fn fill_unreachable(&mut self, start: IVec2) {
+        let mut reachable = vec![false; self.open.len()];
+        let mut stack = vec![start];
+        while let Some(cell) = stack.pop() {
+            if !self.is_open(cell.x, cell.y) {
+                continue;
+            }
+            let index = cell.y as usize * self.width + cell.x as usize;
+            if reachable[index] {
+                continue;
+            }
+            reachable[index] = true;
+            stack.extend([IVec2::X, IVec2::NEG_X, IVec2::Y, IVec2::NEG_Y].map(|step| cell + step));
+        }
+        for (open, reachable) in self.open.iter_mut().zip(reachable) {
+            *open &= reachable;
+        }
+    }
+
+    // Cuts the map into sectors that become its rooms, and makes sure each one has a clearing in
+    // the middle joined to its neighbours
+    fn into_layout(mut self, rng: &mut StdRng, treasure_rooms: usize) -> DungeonLayout {
+        let bounds = Rect::from_corners(
+            self.origin,
+            self.origin + Vec2::new(self.width as f32, self.height as f32) * TILE_SIZE,
+        );
+        let columns = ((bounds.width() / SECTOR_SIZE).round() as usize).max(1);
+        let rows = ((bounds.height() / SECTOR_SIZE).round() as usize).max(1);
+        let column_edges: Vec<usize> = (0..=columns).map(|i| i * self.width / columns).collect();
+        let row_edges: Vec<usize> = (0..=rows).map(|i| i * self.height / rows).collect();
+        let sector = |column: usize, row: usize| row * columns + column;
+
+        let centers: Vec<IVec2> = (0..rows)
+            .flat_map(|row| (0..columns).map(move |column| (column, row)))
+            .map(|(column, row)| {
+                IVec2::new(
+                    ((column_edges[column] + column_edges[column + 1]) / 2) as i32,
+                    ((row_edges[row] + row_edges[row + 1]) / 2) as i32,
+                )
+            })
+            .collect();
+
+        let mut adjacency = vec![Vec::new(); columns * rows];
+        for row in 0..rows {
+            for column in 0..columns {
+                let mut neighbours = Vec::new();
+                if column + 1 < columns {
+                    neighbours.push(sector(column + 1, row));
+                }
+                if row + 1 < rows {
+                    neighbours.push(sector(column, row + 1));
+                }
+                for neighbour in neighbours {
+                    let here = sector(column, row);
+                    self.carve_tunnel(centers[here], centers[neighbour]);
+                    adjacency[here].push(neighbour);
+                    adjacency[neighbour].push(here);
+                }
+            }
+        }
+        for &center in &centers {
+            self.carve_circle(center, CLEARING_RADIUS);
+        }
+        self.fill_unreachable(centers[0]);
+
+        // The rock itself is the walls, so it goes straight into the tile grid
+        let mut tiles = TileGrid::new(self.center_of(0, 0), self.width, self.height);
+        for y in 0..self.height as i32 {
+            for x in 0..self.width as i32 {
+                let kind = if self.is_open(x, y) {
+                    TileKind::Floor
+                } else {
+                    TileKind::Wall
+                };
+                tiles.set(IVec2::new(x, y), kind);
+            }
+        }
+
+        let mut leaves = Vec::new();
+        let mut rooms = Vec::new();
+        for row in 0..rows {
+            for column in 0..columns {
+                let (x0, x1) = (column_edges[column], column_edges[column + 1]);
+                let (y0, y1) = (row_edges[row], row_edges[row + 1]);
+                let rect = Rect::from_corners(
+                    self.origin + Vec2::new(x0 as f32, y0 as f32) * TILE_SIZE,
+                    self.origin + Vec2::new(x1 as f32, y1 as f32) * TILE_SIZE,
+                );
+
+                let mut floor = Vec::new();
+                for y in y0 as i32..y1 as i32 {
+                    for x in x0 as i32..x1 as i32 {
+                        if self.is_open(x, y) && self.rock_around(x, y) == 0 {
+                            floor.push(self.center_of(x, y));
+                        }
+                    }
+                }
+                let features = floor
+                    .choose_multiple(rng, ENEMY_SPAWN_POINTS)
+                    .map(|&position| (position, MapTile::EnemySpawn))
+                    .collect();
+
+                leaves.push(Leaf {
+                    x: rect.min.x,
+                    y: rect.min.y,
+                    width: rect.width(),
+                    height: rect.height(),
+                    child_split: false,
+                    children: None,
+                });
+                rooms.push(Room {
+                    leaf: leaves.len() - 1,
+                    rect,
+                    is_left_edge: column == 0,
+                    is_right_edge: column == columns - 1,
+                    is_top_edge: row == rows - 1,
+                    is_bottom_edge: row == 0,
+                    doors: Vec::new(),
+                    kind: RoomKind::Normal,
+                    features,
+                    walled: false,
+                });
+            }
+        }
+
+        let mut layout = DungeonLayout {
+            bounds,
+            leaves,
+            rooms,
+            adjacency,
+            corridors: Vec::new(),
+            tiles: Some(tiles),
+        };
+        layout.assign_room_kinds(rng, treasure_rooms);
+        layout
+    }
+}
@@ -1,5 +1,6 @@
 use bevy::prelude::*;
 
+use crate::controls::{not_paused, Action, ActionInput};
 use crate::{Money, Player};
 
 pub struct PigPlugin;
@@ -7,7 +8,7 @@ pub struct PigPlugin;
 impl Plugin for PigPlugin {
     fn build(&self, app: &mut App) {
         app.add_systems(Startup, spawn_pig_parent)
-            .add_systems(Update, (spawn_pig, pig_lifetime))
+            .add_systems(Update, (spawn_pig.run_if(not_paused), pig_lifetime))
             .register_type::<Pig>();
     }
 }
@@ -28,12 +29,12 @@ fn spawn_pig_parent(mut commands: Commands) {
 fn spawn_pig(
     mut commands: Commands,
     asset_server: Res<AssetServer>,
-    input: Res<Input<KeyCode>>,
+    input: ActionInput,
     mut money: ResMut<Money>,
     player: Query<&Transform, With<Player>>,
     parent: Query<Entity, With<PigParent>>,
 ) {
-    if !input.just_pressed(KeyCode::Space) {
+    if !input.just_pressed(Action::SpawnPig) {
         return;
     }
 
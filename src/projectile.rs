@@ -1,8 +1,28 @@
+use std::f32::consts::TAU;
+use std::ops::Add;
+
+use bevy::ecs::system::{EntityCommands, SystemParam};
 use bevy::prelude::*;
+use bevy::window::PrimaryWindow;
 
-use crate::enemy::Enemy;
+use crate::camera::{GameCamera, ShakeCamera};
+use crate::collision::{Collider, SpatialHash};
+use crate::controls::{not_paused, Action, ActionInput};
+use crate::enemy::{Enemy, HIT_SHAKE};
+use crate::seed::DungeonRng;
+use crate::tile_grid::{TileGrid, TileKind};
+use crate::weapon::Weapon;
 use crate::Player;
 
+const PROJECTILE_RADIUS: f32 = 4.;
+const IMPACT_SIZE: f32 = 6.;
+const IMPACT_SECONDS: f32 = 0.15;
+const IMPACT_COLOR: Color = Color::rgb(1., 0.9, 0.6);
+// How far the pieces of a split projectile fly
+const SPLIT_RANGE: f32 = 48.;
+const EXPLOSION_SHAKE: f32 = 0.15;
+const ENEMY_PROJECTILE_COLOR: Color = Color::rgb(1., 0.4, 0.4);
+
 pub struct ProjectilePlugin;
 
 impl Plugin for ProjectilePlugin {
@@ -10,9 +30,24 @@ impl Plugin for ProjectilePlugin {
         app.add_systems(Startup, create_projectile_parent)
             .add_systems(
                 Update,
-                (create_projectile, projectile_lifetime, projectile_collision),
+                (
+                    create_projectile.run_if(not_paused),
+                    home_projectiles,
+                    projectile_lifetime,
+                    projectile_collision,
+                    projectile_death,
+                    impact_lifetime,
+                )
+                    .chain(),
             )
-            .register_type::<Projectile>();
+            .register_type::<Projectile>()
+            .register_type::<ProjectileModifiers>()
+            .register_type::<Pierce>()
+            .register_type::<Ricochet>()
+            .register_type::<Homing>()
+            .register_type::<Explosive>()
+            .register_type::<Split>()
+            .register_type::<Impact>();
     }
 }
 
@@ -22,7 +57,162 @@ impl Plugin for ProjectilePlugin {
 pub struct Projectile {
     pub lifetime: Timer,
     pub speed: f32,
+    // Normalized, and the sprite is rotated to face it
     pub direction: Vec2,
+    pub damage: f32,
+    pub faction: Faction,
+}
+
+impl Projectile {
+    pub fn new(direction: Vec2, speed: f32, damage: f32, range: f32, faction: Faction) -> Self {
+        Projectile {
+            lifetime: Timer::from_seconds(range / speed, TimerMode::Once),
+            speed,
+            direction,
+            damage,
+            faction,
+        }
+    }
+}
+
+// Who fired a projectile. Projectiles only hurt the other side
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Reflect)]
+pub enum Faction {
+    #[default]
+    Player,
+    Enemy,
+}
+
+impl Faction {
+    pub fn hurts_player(self) -> bool {
+        self == Faction::Enemy
+    }
+
+    fn tint(self) -> Color {
+        match self {
+            Faction::Player => Color::WHITE,
+            Faction::Enemy => ENEMY_PROJECTILE_COLOR,
+        }
+    }
+}
+
+// Extra behaviour for projectiles, added together from the weapon and the player's pickups.
+// Each one that's set becomes a component on the projectiles fired
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq, Reflect)]
+#[reflect(Component)]
+pub struct ProjectileModifiers {
+    pub pierce: u32,
+    pub ricochet: u32,
+    // Radians per second, 0 for no homing
+    pub homing: f32,
+    // 0 for no explosion
+    pub explosion_radius: f32,
+    pub explosion_damage: f32,
+    pub split: u32,
+}
+
+impl ProjectileModifiers {
+    pub const NONE: ProjectileModifiers = ProjectileModifiers {
+        pierce: 0,
+        ricochet: 0,
+        homing: 0.,
+        explosion_radius: 0.,
+        explosion_damage: 0.,
+        split: 0,
+    };
+
+    fn insert_into(&self, projectile: &mut EntityCommands) {
+        if self.pierce > 0 {
+            projectile.insert(Pierce {
+                remaining: self.pierce,
+                hit: Vec::new(),
+            });
+        }
+        if self.ricochet > 0 {
+            projectile.insert(Ricochet {
+                remaining: self.ricochet,
+            });
+        }
+        if self.homing > 0. {
+            projectile.insert(Homing {
+                turn_rate: self.homing,
+            });
+        }
+        if self.explosion_radius > 0. {
+            projectile.insert(Explosive {
+                radius: self.explosion_radius,
+                damage: self.explosion_damage,
+            });
+        }
+        if self.split > 0 {
+            projectile.insert(Split { count: self.split });
+        }
+    }
+}
+
+impl Add for ProjectileModifiers {
+    type Output = ProjectileModifiers;
+
+    fn add(self, other: ProjectileModifiers) -> ProjectileModifiers {
+        ProjectileModifiers {
+            pierce: self.pierce + other.pierce,
+            ricochet: self.ricochet + other.ricochet,
+            homing: self.homing + other.homing,
+            explosion_radius: self.explosion_radius + other.explosion_radius,
+            explosion_damage: self.explosion_damage + other.explosion_damage,
+            split: self.split + other.split,
+        }
+    }
+}
+
+// Goes through this many more enemies instead of breaking on them
+#[derive(Component, Default, Reflect)]
+#[reflect(Component)]
+pub struct Pierce {
+    pub remaining: u32,
+    // Enemies already gone through, so each is only hit once
+    pub hit: Vec<Entity>,
+}
+
+// Bounces off walls this many more times instead of breaking on them
+#[derive(Component, Default, Reflect)]
+#[reflect(Component)]
+pub struct Ricochet {
+    pub remaining: u32,
+}
+
+// Turns towards the nearest enemy, up to `turn_rate` radians a second
+#[derive(Component, Default, Reflect)]
+#[reflect(Component)]
+pub struct Homing {
+    pub turn_rate: f32,
+}
+
+// Damages every enemy within `radius` when the projectile breaks
+#[derive(Component, Default, Reflect)]
+#[reflect(Component)]
+pub struct Explosive {
+    pub radius: f32,
+    pub damage: f32,
+}
+
+// Breaks into `count` smaller projectiles fanned out around it
+#[derive(Component, Default, Reflect)]
+#[reflect(Component)]
+pub struct Split {
+    pub count: u32,
+}
+
+// Marks a projectile that's broken this frame, for `projectile_death` to clean up
+#[derive(Component)]
+struct Spent;
+
+// Spark left where a projectile hits a wall, or its explosion
+#[derive(Component, Default, Reflect)]
+#[reflect(Component)]
+pub struct Impact {
+    pub timer: Timer,
+    pub size: f32,
 }
 
 #[derive(Component)]
@@ -36,67 +226,212 @@ fn create_projectile_parent(mut commands: Commands) {
     ));
 }
 
+// Everything the player can aim with: the arrow keys, the mouse and the right stick
+#[derive(SystemParam)]
+struct PlayerAim<'w, 's> {
+    input: ActionInput<'w>,
+    windows: Query<'w, 's, &'static Window, With<PrimaryWindow>>,
+    cameras: Query<'w, 's, (&'static Camera, &'static GlobalTransform), With<GameCamera>>,
+}
+
+impl PlayerAim<'_, '_> {
+    // Which way the player is shooting from `position`, or `None` if they aren't
+    fn direction(&self, position: Vec2) -> Option<Vec2> {
+        // Every held arrow counts, so two at once shoot diagonally
+        let axis = |negative: Action, positive: Action| {
+            self.input.pressed(positive) as i32 as f32 - self.input.pressed(negative) as i32 as f32
+        };
+        let keys = Vec2::new(
+            axis(Action::ShootLeft, Action::ShootRight),
+            axis(Action::ShootDown, Action::ShootUp),
+        );
+        if keys != Vec2::ZERO {
+            keys.try_normalize()
+        } else if self.input.pressed(Action::Shoot) {
+            self.cursor()
+                .and_then(|cursor| (cursor - position).try_normalize())
+        } else {
+            // Twin-stick shooting, in whatever direction the right stick points
+            self.input.aim()
+        }
+    }
+
+    // Where the cursor is in the world, if it's over the window
+    fn cursor(&self) -> Option<Vec2> {
+        let cursor = self.windows.get_single().ok()?.cursor_position()?;
+        let (camera, camera_transform) = self.cameras.get_single().ok()?;
+        camera.viewport_to_world_2d(camera_transform, cursor)
+    }
+}
+
+// Where new projectiles go and what they're drawn with
+#[derive(SystemParam)]
+struct ProjectileSpawning<'w, 's> {
+    parent: Query<'w, 's, Entity, With<ProjectileParent>>,
+    asset_server: Res<'w, AssetServer>,
+}
+
 fn create_projectile(
     mut commands: Commands,
-    asset_server: Res<AssetServer>,
-    input: Res<Input<KeyCode>>,
-    player: Query<&Transform, With<Player>>,
-    parent: Query<Entity, With<ProjectileParent>>,
+    spawning: ProjectileSpawning,
+    aim: PlayerAim,
+    time: Res<Time>,
+    mut cooldown: Local<f32>,
+    player: Query<(&Transform, &Player, &Weapon, &ProjectileModifiers)>,
+    mut dungeon_rng: ResMut<DungeonRng>,
 ) {
-    if !input.any_just_pressed([KeyCode::Up, KeyCode::Down, KeyCode::Left, KeyCode::Right]) {
+    *cooldown -= time.delta_seconds();
+    let Ok((player_transform, player, weapon, pickups)) = player.get_single() else {
         return;
-    }
-    if !input.any_just_pressed([KeyCode::Up, KeyCode::Down, KeyCode::Left, KeyCode::Right]) {
+    };
+    if *cooldown > 0. {
         return;
     }
+    let Some(aim) = aim.direction(player_transform.translation.xy()) else {
+        return;
+    };
+    *cooldown = 1. / weapon.fire_rate;
 
-    let player_transform = player.single();
-    let parent = parent.single();
-    let texture = asset_server.load("bullet2.png");
-    let mut dir = Vec2::ZERO;
-    if input.pressed(KeyCode::Up) {
-        dir = Vec2::Y;
-    }
-    if input.pressed(KeyCode::Down) {
-        dir = Vec2::NEG_Y;
-    }
-    if input.pressed(KeyCode::Left) {
-        dir = Vec2::NEG_X;
-    }
-    if input.pressed(KeyCode::Right) {
-        dir = Vec2::X;
-    }
+    let parent = spawning.parent.single();
+    let texture: Handle<Image> = spawning.asset_server.load(weapon.sprite);
+    let modifiers = weapon.modifiers + *pickups;
+    let directions = weapon.shot_directions(aim, &mut dungeon_rng.combat);
 
     commands.entity(parent).with_children(|commands| {
-        commands.spawn((
-            SpriteBundle {
-                texture,
-                transform: *player_transform,
+        for dir in directions {
+            let mut projectile = spawn_projectile(
+                commands,
+                texture.clone(),
+                player_transform.translation,
+                Projectile::new(
+                    dir,
+                    weapon.speed,
+                    weapon.damage * player.damage_multiplier,
+                    weapon.range,
+                    Faction::Player,
+                ),
+            );
+            modifiers.insert_into(&mut projectile);
+        }
+    });
+}
+
+fn facing(direction: Vec2) -> Quat {
+    Quat::from_rotation_z(direction.y.atan2(direction.x))
+}
+
+// Spawns a projectile under `ProjectileParent`, tinted by who fired it
+pub fn spawn_projectile<'w, 's, 'a>(
+    commands: &'a mut ChildBuilder<'w, 's, '_>,
+    texture: Handle<Image>,
+    translation: Vec3,
+    projectile: Projectile,
+) -> EntityCommands<'w, 's, 'a> {
+    commands.spawn((
+        SpriteBundle {
+            sprite: Sprite {
+                color: projectile.faction.tint(),
                 ..default()
             },
-            Projectile {
-                lifetime: Timer::from_seconds(4.0, TimerMode::Once),
-                speed: 200.0,
-                direction: dir,
-            },
-            Name::new("Bullet"),
-        ));
-    });
+            texture,
+            transform: Transform::from_translation(translation)
+                .with_rotation(facing(projectile.direction)),
+            ..default()
+        },
+        projectile,
+        Collider::circle(PROJECTILE_RADIUS),
+        Name::new("Bullet"),
+    ))
+}
+
+// Anything a bullet can home in on, depending on who fired it
+type HomingTarget = Or<(With<Enemy>, With<Player>)>;
+
+fn home_projectiles(
+    time: Res<Time>,
+    mut projectiles: Query<(&mut Transform, &mut Projectile, &Homing)>,
+    targets: Query<(&GlobalTransform, Has<Player>), HomingTarget>,
+) {
+    for (mut transform, mut projectile, homing) in &mut projectiles {
+        let position = transform.translation.xy();
+        let nearest = targets
+            .iter()
+            .filter(|(_, is_player)| *is_player == projectile.faction.hurts_player())
+            .map(|(target, _)| target.translation().xy())
+            .min_by(|a, b| {
+                a.distance_squared(position)
+                    .total_cmp(&b.distance_squared(position))
+            });
+        let Some(target) = nearest.and_then(|target| (target - position).try_normalize()) else {
+            continue;
+        };
+        let max_turn = homing.turn_rate * time.delta_seconds();
+        let turn = projectile
+            .direction
+            .angle_between(target)
+            .clamp(-max_turn, max_turn);
+        projectile.direction = Vec2::from_angle(turn).rotate(projectile.direction);
+        transform.rotation = facing(projectile.direction);
+    }
 }
 
 fn projectile_lifetime(
     mut commands: Commands,
     time: Res<Time>,
-    mut projectiles: Query<(&mut Transform, Entity, &mut Projectile)>,
+    mut projectiles: Query<(
+        &mut Transform,
+        Entity,
+        &mut Projectile,
+        &Collider,
+        Option<&mut Ricochet>,
+    )>,
     parent: Query<Entity, With<ProjectileParent>>,
+    grid: Res<TileGrid>,
 ) {
     let parent = parent.single();
 
-    for (mut projectile_transform, projectile_entity, mut projectile) in &mut projectiles {
+    for (mut projectile_transform, projectile_entity, mut projectile, collider, ricochet) in
+        &mut projectiles
+    {
         projectile.lifetime.tick(time.delta());
 
         let movement_amount = projectile.speed * projectile.direction * time.delta_seconds();
-        projectile_transform.translation += Vec3::new(movement_amount.x, movement_amount.y, 0.);
+        let from = projectile_transform.translation.xy();
+        let to = from + movement_amount;
+        if !collider.hits_tiles(to, &grid, TileKind::blocks_projectiles) {
+            projectile_transform.translation += movement_amount.extend(0.);
+        } else if let Some(mut ricochet) = ricochet.filter(|ricochet| ricochet.remaining > 0) {
+            // Flip whichever way ran into the wall, staying put for this frame
+            ricochet.remaining -= 1;
+            let mut direction = projectile.direction;
+            let blocked_x =
+                collider.hits_tiles(Vec2::new(to.x, from.y), &grid, TileKind::blocks_projectiles);
+            let blocked_y =
+                collider.hits_tiles(Vec2::new(from.x, to.y), &grid, TileKind::blocks_projectiles);
+            if blocked_x || !blocked_y {
+                direction.x = -direction.x;
+            }
+            if blocked_y || !blocked_x {
+                direction.y = -direction.y;
+            }
+            projectile.direction = direction;
+            projectile_transform.rotation = facing(direction);
+            spawn_impact(
+                &mut commands,
+                parent,
+                projectile_transform.translation,
+                IMPACT_SIZE,
+            );
+        } else {
+            spawn_impact(
+                &mut commands,
+                parent,
+                projectile_transform.translation,
+                IMPACT_SIZE,
+            );
+            commands.entity(projectile_entity).insert(Spent);
+            continue;
+        }
 
         /*         let mut movement_amount = projectile.speed * projectile.lifetime.tick(time.delta());
         if input.pressed(KeyCode::Up) {
@@ -112,36 +447,174 @@ fn projectile_lifetime(
             projectile_entity.translation.x -= movement_amount;
         } */
 
-
         if projectile.lifetime.finished() {
-            commands
-                .entity(parent)
-                .remove_children(&[projectile_entity]);
-            commands.entity(projectile_entity).despawn();
+            commands.entity(projectile_entity).insert(Spent);
         }
     }
 }
 
+fn spawn_impact(commands: &mut Commands, parent: Entity, translation: Vec3, size: f32) {
+    commands.entity(parent).with_children(|commands| {
+        commands.spawn((
+            SpriteBundle {
+                sprite: Sprite {
+                    color: IMPACT_COLOR,
+                    custom_size: Some(Vec2::splat(size)),
+                    ..default()
+                },
+                transform: Transform::from_translation(translation),
+                ..default()
+            },
+            Impact {
+                timer: Timer::from_seconds(IMPACT_SECONDS, TimerMode::Once),
+                size,
+            },
+            Name::new("Impact"),
+        ));
+    });
+}
+
+// Shrinks and fades out impact sparks, then removes them
+fn impact_lifetime(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut impacts: Query<(Entity, &mut Impact, &mut Sprite)>,
+    parent: Query<Entity, With<ProjectileParent>>,
+) {
+    let parent = parent.single();
+    for (entity, mut impact, mut sprite) in &mut impacts {
+        impact.timer.tick(time.delta());
+        let left = impact.timer.percent_left();
+        sprite.color = IMPACT_COLOR.with_a(left);
+        sprite.custom_size = Some(Vec2::splat(impact.size * left));
+        if impact.timer.finished() {
+            commands.entity(parent).remove_children(&[entity]);
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+// Whatever projectiles can hurt: enemies for the player's, and the player for enemies'
+#[derive(SystemParam)]
+struct Targets<'w, 's> {
+    enemies: Query<'w, 's, &'static mut Enemy>,
+    players: Query<'w, 's, &'static mut Player, Without<Enemy>>,
+    shake: EventWriter<'w, ShakeCamera>,
+}
+
+impl Targets<'_, '_> {
+    fn can_hit(&self, faction: Faction, entity: Entity) -> bool {
+        if faction.hurts_player() {
+            self.players.contains(entity)
+        } else {
+            self.enemies.contains(entity)
+        }
+    }
+
+    fn damage(&mut self, faction: Faction, entity: Entity, amount: f32) {
+        if !faction.hurts_player() {
+            if let Ok(mut enemy) = self.enemies.get_mut(entity) {
+                enemy.health -= amount;
+            }
+        } else if let Ok(mut player) = self.players.get_mut(entity) {
+            player.health -= amount;
+            self.shake.send(ShakeCamera(amount * HIT_SHAKE));
+        }
+    }
+}
+
+type LiveProjectile<'a> = (
+    &'a Transform,
+    Entity,
+    &'a Collider,
+    &'a Projectile,
+    Option<&'a mut Pierce>,
+);
+
 fn projectile_collision(
     mut commands: Commands,
-    mut enemies: Query<(&mut Transform, &mut Enemy), Without<Projectile>>,
-    projectiles: Query<(&mut Transform, Entity), With<Projectile>>,
+    mut targets: Targets,
+    mut projectiles: Query<LiveProjectile, Without<Spent>>,
+    hash: Res<SpatialHash>,
+) {
+    for (projectile_transform, projectile_entity, collider, projectile, mut pierce) in
+        &mut projectiles
+    {
+        let hit = hash
+            .overlapping(projectile_transform.translation.xy(), collider)
+            .into_iter()
+            .find(|&entity| {
+                targets.can_hit(projectile.faction, entity)
+                    && !pierce
+                        .as_ref()
+                        .is_some_and(|pierce| pierce.hit.contains(&entity))
+            });
+        // Each bullet only hits one target a frame
+        let Some(entity) = hit else {
+            continue;
+        };
+        targets.damage(projectile.faction, entity, projectile.damage);
+        match pierce.as_mut().filter(|pierce| pierce.remaining > 0) {
+            Some(pierce) => {
+                pierce.remaining -= 1;
+                pierce.hit.push(entity);
+            }
+            None => {
+                commands.entity(projectile_entity).insert(Spent);
+            }
+        }
+    }
+}
+
+type SpentProjectile<'a> = (
+    Entity,
+    &'a Transform,
+    &'a Projectile,
+    &'a Handle<Image>,
+    Option<&'a Explosive>,
+    Option<&'a Split>,
+);
+
+// Removes broken projectiles, setting off any explosion or split they carry
+fn projectile_death(
+    mut commands: Commands,
+    projectiles: Query<SpentProjectile, With<Spent>>,
+    mut targets: Targets,
     parent: Query<Entity, With<ProjectileParent>>,
+    hash: Res<SpatialHash>,
 ) {
     let parent = parent.single();
-    for (enemy_transform, mut enemy) in &mut enemies {
-        for (projectile_transform, projectile_entity) in projectiles.iter() {
-            let distance = enemy_transform
-                .translation
-                .distance(projectile_transform.translation);
-            if distance < 10. {
-                enemy.health -= 25.0;
-                commands
-                    .entity(parent)
-                    .remove_children(&[projectile_entity]);
-                commands.entity(projectile_entity).despawn();
+    for (entity, transform, projectile, texture, explosive, split) in &projectiles {
+        let position = transform.translation;
+        if let Some(explosive) = explosive {
+            let blast = Collider::circle(explosive.radius);
+            for hit in hash.overlapping(position.xy(), &blast) {
+                targets.damage(projectile.faction, hit, explosive.damage);
             }
+            spawn_impact(&mut commands, parent, position, explosive.radius * 2.);
+            targets.shake.send(ShakeCamera(EXPLOSION_SHAKE));
+        }
+        if let Some(split) = split {
+            commands.entity(parent).with_children(|commands| {
+                for i in 0..split.count {
+                    let angle = TAU * i as f32 / split.count as f32;
+                    spawn_projectile(
+                        commands,
+                        texture.clone(),
+                        position,
+                        Projectile::new(
+                            Vec2::from_angle(angle).rotate(projectile.direction),
+                            projectile.speed,
+                            projectile.damage / 2.,
+                            SPLIT_RANGE,
+                            projectile.faction,
+                        ),
+                    );
+                }
+            });
         }
+        commands.entity(parent).remove_children(&[entity]);
+        commands.entity(entity).despawn();
     }
 }
 /*
@@ -0,0 +1,129 @@
+use bevy::asset::{LoadedFolder, RecursiveDependencyLoadState};
+use bevy::prelude::*;
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+use crate::layout::{DungeonLayout, RoomKind, DOOR_WIDTH};
+use crate::map_file::{MapFile, MapTile};
+use crate::tilemap::TILE_SIZE;
+
+// Templates are never blown up more than this, so a small template doesn't turn into a few
+// enormous blocks in a big room
+const MAX_TEMPLATE_SCALE: usize = 2;
+// Anything solid this close to a door is left out so the door can't be blocked
+const DOOR_CLEARANCE: f32 = DOOR_WIDTH + TILE_SIZE * 2.;
+
+// Every map file in `assets/rooms/`, drawn with the same legend as `dungeon.map.txt`
+#[derive(Resource)]
+pub struct RoomTemplates {
+    folder: Handle<LoadedFolder>,
+}
+
+impl RoomTemplates {
+    // Templates that have finished loading. Empty until the folder is loaded
+    pub fn loaded<'a>(
+        &self,
+        folders: &'a Assets<LoadedFolder>,
+        maps: &'a Assets<MapFile>,
+    ) -> Vec<&'a MapFile> {
+        let Some(folder) = folders.get(&self.folder) else {
+            return Vec::new();
+        };
+        folder
+            .handles
+            .iter()
+            .filter(|handle| handle.type_id() == std::any::TypeId::of::<MapFile>())
+            .filter_map(|handle| maps.get(handle.clone().typed::<MapFile>()))
+            .collect()
+    }
+}
+
+// Run condition that holds off generating a floor until every template has loaded, or failed to
+pub fn room_templates_loaded(
+    templates: Res<RoomTemplates>,
+    asset_server: Res<AssetServer>,
+) -> bool {
+    matches!(
+        asset_server.get_recursive_dependency_load_state(&templates.folder),
+        Some(RecursiveDependencyLoadState::Loaded | RecursiveDependencyLoadState::Failed)
+    )
+}
+
+pub fn load_room_templates(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(RoomTemplates {
+        folder: asset_server.load_folder("rooms"),
+    });
+}
+
+// Picks a template that fits inside the walls of each normal room, centres it, and records its
+// tiles as the room's features
+pub fn apply_room_templates(
+    layout: &mut DungeonLayout,
+    templates: &[&MapFile],
+    rng: &mut impl Rng,
+) {
+    for room in layout.rooms.iter_mut() {
+        // Caves have no clear floor to lay a template out on
+        if room.kind != RoomKind::Normal || !room.walled {
+            continue;
+        }
+
+        // Leave a tile free for the wall on each side
+        let columns = ((room.rect.width() / TILE_SIZE) as usize).saturating_sub(2);
+        let rows = ((room.rect.height() / TILE_SIZE) as usize).saturating_sub(2);
+        let fitting: Vec<&MapFile> = templates
+            .iter()
+            .copied()
+            .filter(|template| template.width <= columns && template.height <= rows)
+            .filter(|template| template.width > 0 && template.height > 0)
+            .collect();
+        let Some(template) = fitting.choose(rng) else {
+            continue;
+        };
+
+        let scale = (columns / template.width)
+            .min(rows / template.height)
+            .clamp(1, MAX_TEMPLATE_SCALE);
+        let size =
+            Vec2::new(template.width as f32, template.height as f32) * TILE_SIZE * scale as f32;
+        // Centre of the template's bottom left tile, kept on the tile grid
+        let origin = room.rect.min
+            + ((room.rect.size() - size) / 2. / TILE_SIZE).floor() * TILE_SIZE
+            + TILE_SIZE;
+
+        for row in 0..template.height * scale {
+            for column in 0..template.width * scale {
+                let tile = template.get(column / scale, row / scale);
+                let position = origin
+                    + Vec2::new(column as f32, (template.height * scale - 1 - row) as f32)
+                        * TILE_SIZE;
+
+                match tile {
+                    MapTile::Obstacle | MapTile::Pit | MapTile::Wall => {
+                        if room
+                            .doors
+                            .iter()
+                            .any(|door| door.distance(position) < DOOR_CLEARANCE)
+                        {
+                            continue;
+                        }
+                        // Walls inside a room are just obstacles
+                        let tile = if tile == MapTile::Pit {
+                            MapTile::Pit
+                        } else {
+                            MapTile::Obstacle
+                        };
+                        room.features.push((position, tile));
+                    }
+                    // Points only go in once per scaled up tile
+                    MapTile::EnemySpawn | MapTile::Drop(_) => {
+                        if column % scale == 0 && row % scale == 0 {
+                            room.features.push((position, tile));
+                        }
+                    }
+                    MapTile::Empty | MapTile::Floor | MapTile::Door | MapTile::PlayerSpawn => {}
+                }
+            }
+        }
+    }
+}
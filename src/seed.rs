@@ -0,0 +1,86 @@
+use bevy::prelude::*;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::cli_arg;
+use crate::main_menu::GameState;
+
+// Independent random streams derived from the same seed, so that e.g. how many
+// enemies spawned never changes the layout of the next room
+pub const MAP_STREAM: u64 = 0;
+pub const SPAWN_STREAM: u64 = 1;
+pub const LOOT_STREAM: u64 = 2;
+pub const COMBAT_STREAM: u64 = 3;
+// Each floor gets its own block of streams
+const FLOOR_STREAMS: u64 = 16;
+
+pub struct SeedPlugin;
+
+impl Plugin for SeedPlugin {
+    fn build(&self, app: &mut App) {
+        let seed = DungeonSeed::from_env();
+        app.insert_resource(seed)
+            .insert_resource(DungeonRng::new(seed))
+            .add_systems(OnEnter(GameState::Game), reseed)
+            .register_type::<DungeonSeed>();
+    }
+}
+
+#[derive(Resource, Clone, Copy, Debug, Default, PartialEq, Eq, Reflect)]
+#[reflect(Resource)]
+pub struct DungeonSeed(pub u64);
+
+impl DungeonSeed {
+    // Looks for `--seed <n>` / `--seed=<n>` on the command line, then the `DUNGEON_SEED`
+    // environment variable, and picks a random seed if neither is set
+    pub fn from_env() -> Self {
+        if let Some(seed) = cli_arg("seed").and_then(|v| v.parse().ok()) {
+            return DungeonSeed(seed);
+        }
+
+        if let Some(seed) = std::env::var("DUNGEON_SEED")
+            .ok()
+            .and_then(|v| v.parse().ok())
+        {
+            return DungeonSeed(seed);
+        }
+
+        DungeonSeed::random()
+    }
+
+    pub fn random() -> Self {
+        DungeonSeed(rand::thread_rng().gen())
+    }
+
+    pub fn rng(&self, stream: u64) -> StdRng {
+        StdRng::seed_from_u64(self.0 ^ stream.wrapping_mul(0x9E37_79B9_7F4A_7C15))
+    }
+
+    // A stream for one floor of the dungeon. Floor 1 gets the same numbers as `rng`
+    pub fn floor_rng(&self, stream: u64, floor: u32) -> StdRng {
+        self.rng(stream + (floor.saturating_sub(1) as u64) * FLOOR_STREAMS)
+    }
+}
+
+#[derive(Resource)]
+pub struct DungeonRng {
+    pub spawns: StdRng,
+    pub loot: StdRng,
+    // Rolls made in the middle of a fight, like how far a shot strays
+    pub combat: StdRng,
+}
+
+impl DungeonRng {
+    pub fn new(seed: DungeonSeed) -> Self {
+        DungeonRng {
+            spawns: seed.rng(SPAWN_STREAM),
+            loot: seed.rng(LOOT_STREAM),
+            combat: seed.rng(COMBAT_STREAM),
+        }
+    }
+}
+
+fn reseed(seed: Res<DungeonSeed>, mut rng: ResMut<DungeonRng>) {
+    info!("Dungeon seed: {}", seed.0);
+    *rng = DungeonRng::new(*seed);
+}
@@ -0,0 +1,333 @@
+use std::collections::VecDeque;
+
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+
+use crate::layout::{DungeonLayout, DOOR_WIDTH};
+use crate::main_menu::GameState;
+use crate::map_file::MapTile;
+use crate::player::Player;
+use crate::tilemap::TILE_SIZE;
+
+pub struct TileGridPlugin;
+
+impl Plugin for TileGridPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<TileGrid>()
+            .init_resource::<FlowField>()
+            .add_event::<TileGridBuilt>()
+            .add_systems(
+                Update,
+                update_flow_field.run_if(in_state(GameState::Game)),
+            );
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Reflect)]
+pub enum TileKind {
+    // Outside the level. Nothing is drawn there and nothing walks there
+    #[default]
+    Empty,
+    Floor,
+    Wall,
+    Obstacle,
+    Pit,
+    Door,
+    // A door shut while its room's encounter is running
+    LockedDoor,
+}
+
+impl TileKind {
+    pub fn is_solid(self) -> bool {
+        matches!(
+            self,
+            TileKind::Wall | TileKind::Obstacle | TileKind::Pit | TileKind::LockedDoor
+        )
+    }
+
+    // Bullets fly over pits but stop at anything standing up
+    pub fn blocks_projectiles(self) -> bool {
+        matches!(self, TileKind::Wall | TileKind::Obstacle | TileKind::LockedDoor)
+    }
+
+    pub fn is_walkable(self) -> bool {
+        matches!(self, TileKind::Floor | TileKind::Door)
+    }
+}
+
+impl From<MapTile> for TileKind {
+    fn from(tile: MapTile) -> Self {
+        match tile {
+            MapTile::Empty => TileKind::Empty,
+            MapTile::Wall => TileKind::Wall,
+            MapTile::Obstacle => TileKind::Obstacle,
+            MapTile::Pit => TileKind::Pit,
+            MapTile::Door => TileKind::Door,
+            MapTile::Floor | MapTile::PlayerSpawn | MapTile::EnemySpawn | MapTile::Drop(_) => {
+                TileKind::Floor
+            }
+        }
+    }
+}
+
+// The level as one tile per cell. Map generation and map files write it, and everything that
+// needs to know what's solid or walkable reads it rather than looking at sprites
+#[derive(Resource, Clone, Debug, Default)]
+pub struct TileGrid {
+    // World position of the centre of cell (0, 0), the bottom left one
+    pub origin: Vec2,
+    pub width: usize,
+    pub height: usize,
+    tiles: Vec<TileKind>,
+}
+
+// Sent once a new level has been written to the `TileGrid`, so its sprites can be spawned
+#[derive(Event)]
+pub struct TileGridBuilt;
+
+// Swaps in the grid for a new level and sends `TileGridBuilt` for it
+#[derive(SystemParam)]
+pub struct TileGridWriter<'w> {
+    grid: ResMut<'w, TileGrid>,
+    built: EventWriter<'w, TileGridBuilt>,
+}
+
+impl TileGridWriter<'_> {
+    pub fn set(&mut self, grid: TileGrid) {
+        *self.grid = grid;
+        self.built.send(TileGridBuilt);
+    }
+}
+
+impl TileGrid {
+    pub fn new(origin: Vec2, width: usize, height: usize) -> Self {
+        TileGrid {
+            origin,
+            width,
+            height,
+            tiles: vec![TileKind::Empty; width * height],
+        }
+    }
+
+    // A grid with a cell centred on each corner of `bounds`
+    pub fn covering(bounds: Rect) -> Self {
+        let cells = (bounds.size() / TILE_SIZE).round().as_uvec2() + 1;
+        TileGrid::new(bounds.min, cells.x as usize, cells.y as usize)
+    }
+
+    pub fn world_to_grid(&self, position: Vec2) -> IVec2 {
+        ((position - self.origin) / TILE_SIZE).round().as_ivec2()
+    }
+
+    pub fn grid_to_world(&self, cell: IVec2) -> Vec2 {
+        self.origin + cell.as_vec2() * TILE_SIZE
+    }
+
+    pub fn contains(&self, cell: IVec2) -> bool {
+        cell.x >= 0
+            && cell.y >= 0
+            && (cell.x as usize) < self.width
+            && (cell.y as usize) < self.height
+    }
+
+    fn index(&self, cell: IVec2) -> usize {
+        cell.y as usize * self.width + cell.x as usize
+    }
+
+    pub fn get(&self, cell: IVec2) -> TileKind {
+        if self.contains(cell) {
+            self.tiles[self.index(cell)]
+        } else {
+            TileKind::Empty
+        }
+    }
+
+    pub fn set(&mut self, cell: IVec2, kind: TileKind) {
+        if self.contains(cell) {
+            let index = self.index(cell);
+            self.tiles[index] = kind;
+        }
+    }
+
+    pub fn cells(&self) -> impl Iterator<Item = (IVec2, TileKind)> + '_ {
+        self.tiles.iter().enumerate().map(|(index, &kind)| {
+            let cell = IVec2::new((index % self.width) as i32, (index / self.width) as i32);
+            (cell, kind)
+        })
+    }
+
+    // Shuts or opens the door tiles belonging to each of `doors`
+    pub fn set_doors_locked(&mut self, doors: &[Vec2], locked: bool) {
+        for &door in doors {
+            let center = self.world_to_grid(door);
+            let reach = (DOOR_WIDTH / TILE_SIZE).ceil() as i32;
+            for y in -reach..=reach {
+                for x in -reach..=reach {
+                    let cell = center + IVec2::new(x, y);
+                    if self.grid_to_world(cell).distance(door) >= DOOR_WIDTH / 2. {
+                        continue;
+                    }
+                    match (self.get(cell), locked) {
+                        (TileKind::Door, true) => self.set(cell, TileKind::LockedDoor),
+                        (TileKind::LockedDoor, false) => self.set(cell, TileKind::Door),
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+
+    // Steps needed to walk from `start` to every cell, or `None` where it can't be reached
+    pub fn distances_from(&self, start: IVec2) -> Vec<Option<u32>> {
+        let mut distances = vec![None; self.tiles.len()];
+        if !self.get(start).is_walkable() {
+            return distances;
+        }
+        let mut queue = VecDeque::new();
+        distances[self.index(start)] = Some(0);
+        queue.push_back(start);
+        while let Some(cell) = queue.pop_front() {
+            let distance = distances[self.index(cell)].unwrap();
+            for step in [IVec2::X, IVec2::NEG_X, IVec2::Y, IVec2::NEG_Y] {
+                let next = cell + step;
+                if !self.get(next).is_walkable() || distances[self.index(next)].is_some() {
+                    continue;
+                }
+                distances[self.index(next)] = Some(distance + 1);
+                queue.push_back(next);
+            }
+        }
+        distances
+    }
+
+    // Rasterises a generated layout. Room edges become walls with door gaps, then features and
+    // corridors are drawn on top
+    pub fn from_layout(layout: &DungeonLayout) -> Self {
+        let mut grid = layout
+            .tiles
+            .clone()
+            .unwrap_or_else(|| TileGrid::covering(layout.bounds));
+
+        for room in layout.rooms.iter().filter(|room| room.walled) {
+            let min = grid.world_to_grid(room.rect.min);
+            let max = grid.world_to_grid(room.rect.max);
+            for y in min.y..=max.y {
+                for x in min.x..=max.x {
+                    let cell = IVec2::new(x, y);
+                    if x != min.x && x != max.x && y != min.y && y != max.y {
+                        grid.set(cell, TileKind::Floor);
+                        continue;
+                    }
+                    let position = grid.grid_to_world(cell);
+                    // Neighbouring rooms share their edge, so a door one room put there is
+                    // never walled back over by the other
+                    if room
+                        .doors
+                        .iter()
+                        .any(|door| door.distance(position) < DOOR_WIDTH / 2.)
+                    {
+                        grid.set(cell, TileKind::Door);
+                    } else if grid.get(cell) != TileKind::Door {
+                        grid.set(cell, TileKind::Wall);
+                    }
+                }
+            }
+        }
+
+        for room in &layout.rooms {
+            for &(position, feature) in &room.features {
+                if matches!(feature, MapTile::Wall | MapTile::Obstacle | MapTile::Pit) {
+                    let cell = grid.world_to_grid(position);
+                    grid.set(cell, feature.into());
+                }
+            }
+        }
+
+        for corridor in &layout.corridors {
+            for floor in &corridor.floor {
+                let min = grid.world_to_grid(floor.min + TILE_SIZE / 2.);
+                let max = grid.world_to_grid(floor.max - TILE_SIZE / 2.);
+                for y in min.y..=max.y {
+                    for x in min.x..=max.x {
+                        let cell = IVec2::new(x, y);
+                        if grid.get(cell) == TileKind::Empty {
+                            grid.set(cell, TileKind::Floor);
+                        }
+                    }
+                }
+            }
+        }
+        for corridor in &layout.corridors {
+            for &wall in &corridor.walls {
+                let cell = grid.world_to_grid(wall);
+                if grid.get(cell) == TileKind::Empty {
+                    grid.set(cell, TileKind::Wall);
+                }
+            }
+        }
+
+        grid
+    }
+}
+
+// Walking distance from every cell to the player, so enemies can find their way round walls
+#[derive(Resource, Default)]
+pub struct FlowField {
+    target: Option<IVec2>,
+    distances: Vec<Option<u32>>,
+}
+
+impl FlowField {
+    // Direction to head in from `position` to get closer to the player, or `None` when there's
+    // no path from there
+    pub fn direction(&self, grid: &TileGrid, position: Vec2) -> Option<Vec2> {
+        let distance = |cell: IVec2| {
+            if grid.contains(cell) {
+                self.distances.get(grid.index(cell)).copied().flatten()
+            } else {
+                None
+            }
+        };
+        let cell = grid.world_to_grid(position);
+        let here = distance(cell)?;
+        if here == 0 {
+            return None;
+        }
+        let next = [IVec2::X, IVec2::NEG_X, IVec2::Y, IVec2::NEG_Y]
+            .into_iter()
+            .map(|step| cell + step)
+            .filter_map(|next| distance(next).map(|distance| (next, distance)))
+            .min_by_key(|&(_, distance)| distance)?;
+        (grid.grid_to_world(next.0) - position).try_normalize()
+    }
+}
+
+// The level's tiles along with the way across them to the player, for anything chasing them
+#[derive(SystemParam)]
+pub struct Pathfinding<'w> {
+    pub grid: Res<'w, TileGrid>,
+    flow_field: Res<'w, FlowField>,
+}
+
+impl Pathfinding<'_> {
+    // See `FlowField::direction`
+    pub fn direction(&self, position: Vec2) -> Option<Vec2> {
+        self.flow_field.direction(&self.grid, position)
+    }
+}
+
+fn update_flow_field(
+    grid: Res<TileGrid>,
+    players: Query<&Transform, With<Player>>,
+    mut flow_field: ResMut<FlowField>,
+) {
+    let Ok(player) = players.get_single() else {
+        return;
+    };
+    let cell = grid.world_to_grid(player.translation.xy());
+    if flow_field.target == Some(cell) && !grid.is_changed() {
+        return;
+    }
+    flow_field.target = Some(cell);
+    flow_field.distances = grid.distances_from(cell);
+}
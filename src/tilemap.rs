@@ -1,46 +1,206 @@
-
-
+use bevy::ecs::system::SystemParam;
 use bevy::prelude::*;
+use bevy::render::mesh::Indices;
+use bevy::render::render_resource::PrimitiveTopology;
+use bevy::sprite::MaterialMesh2dBundle;
+use bevy::utils::HashMap;
 use crate::main_menu::GameState;
+use crate::tile_grid::{TileGrid, TileGridBuilt, TileKind};
+use crate::tileset::{self, Tileset};
+use crate::wall::Wall;
 
 
 pub const TILE_SIZE: f32 = 8.;
+// Size of the brick tiles drawn under the level
+const BRICK_SIZE: f32 = 32.;
+// Tiles are drawn in square chunks of this many cells a side, one mesh per chunk and layer
+const CHUNK_SIZE: i32 = 16;
 pub struct TileMapPlugin;
 
 #[derive(Component)]
-pub struct TileCollider;
+pub struct TileMapParent;
+
+// One layer of one chunk of the level. Which one is in its `Name`
+#[derive(Component)]
+pub struct TileChunk;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TileLayer {
+    Background,
+    Floor,
+    Pit,
+    Wall,
+    Obstacle,
+}
+
+impl TileLayer {
+    fn of(kind: TileKind) -> Option<TileLayer> {
+        match kind {
+            TileKind::Floor | TileKind::Door | TileKind::LockedDoor => Some(TileLayer::Floor),
+            TileKind::Pit => Some(TileLayer::Pit),
+            TileKind::Wall => Some(TileLayer::Wall),
+            TileKind::Obstacle => Some(TileLayer::Obstacle),
+            TileKind::Empty => None,
+        }
+    }
+
+    fn z(self) -> f32 {
+        match self {
+            TileLayer::Background => -20.,
+            // Under the room quads, so it only shows in corridors and hand-drawn maps
+            TileLayer::Floor => -15.,
+            TileLayer::Pit => -5.,
+            TileLayer::Wall | TileLayer::Obstacle => 10.,
+        }
+    }
+}
 
 impl Plugin for TileMapPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(OnEnter(GameState::Game), create_simple_map);
+        app.add_systems(OnEnter(GameState::Game), spawn_tilemap_parent)
+            .add_systems(Update, create_simple_map.run_if(in_state(GameState::Game)));
     }
 }
 
+fn spawn_tilemap_parent(mut commands: Commands) {
+    commands.spawn((SpatialBundle::default(), TileMapParent, Name::new("Tile Map Parent")));
+}
+
+// What it takes to turn tiles into chunk meshes
+#[derive(SystemParam)]
+struct ChunkAssets<'w> {
+    tileset: Option<Res<'w, Tileset>>,
+    atlases: Res<'w, Assets<TextureAtlas>>,
+    meshes: ResMut<'w, Assets<Mesh>>,
+    materials: ResMut<'w, Assets<ColorMaterial>>,
+}
+
+// Rebuilds every chunk each time a new level is built. Bricks cover the grid's extent, and
+// every other tile goes in the layer for its kind, drawn with the tileset variant that fits its
+// neighbours
 fn create_simple_map(
     mut commands: Commands,
-    assets: Res<AssetServer>,
+    grid: Res<TileGrid>,
+    mut built: EventReader<TileGridBuilt>,
+    // A level built before the tileset has finished loading is drawn once it has
+    mut pending: Local<bool>,
+    parent: Query<Entity, With<TileMapParent>>,
+    mut assets: ChunkAssets,
 ) {
-    let size_x = 3000.;
-    let size_y= 3000.;
-    let mut x = -size_x / 2.;
-    let mut y = -size_y/ 2.;
-
-    while x < size_x / 2. {
-        y += 32.;
-
-        while y < size_y / 2. {
-            let texture = assets.load("brick.png");
-            commands.spawn(SpriteBundle {
-                texture,
-                transform: Transform {
-                    translation: Vec3::new(x, y, -20.0),
-                    scale: Vec3::splat(1.),
-                    ..Default::default()
+    if built.read().count() > 0 {
+        *pending = true;
+    }
+    if !*pending {
+        return;
+    }
+    let ChunkAssets {
+        tileset,
+        atlases,
+        meshes,
+        materials,
+    } = &mut assets;
+    let Some(atlas) = tileset.as_ref().and_then(|tileset| atlases.get(&tileset.atlas)) else {
+        return;
+    };
+    let Ok(parent) = parent.get_single() else {
+        return;
+    };
+    *pending = false;
+    commands.entity(parent).despawn_descendants();
+
+    let chunk_of = |position: Vec2| {
+        ((position - grid.origin) / (TILE_SIZE * CHUNK_SIZE as f32))
+            .floor()
+            .as_ivec2()
+    };
+    let mut layers: HashMap<(IVec2, TileLayer), Vec<(Vec2, usize)>> = HashMap::new();
+
+    let last_cell = IVec2::new(grid.width as i32 - 1, grid.height as i32 - 1);
+    let min = grid.grid_to_world(IVec2::ZERO) - BRICK_SIZE;
+    let max = grid.grid_to_world(last_cell) + BRICK_SIZE;
+    let mut x = min.x;
+    while x < max.x {
+        let mut y = min.y;
+        while y < max.y {
+            let position = Vec2::new(x, y);
+            layers
+                .entry((chunk_of(position), TileLayer::Background))
+                .or_default()
+                .push((position, tileset::BRICK));
+            y += BRICK_SIZE
+        }
+        x += BRICK_SIZE
+    }
+
+    for (cell, kind) in grid.cells() {
+        let (Some(layer), Some(index)) = (TileLayer::of(kind), tileset::tile_index(&grid, cell))
+        else {
+            continue;
+        };
+        let position = grid.grid_to_world(cell);
+        layers
+            .entry((chunk_of(position), layer))
+            .or_default()
+            .push((position, index));
+    }
+
+    let material = materials.add(atlas.texture.clone().into());
+    commands.entity(parent).with_children(|commands| {
+        for ((chunk, layer), tiles) in layers {
+            let origin = grid.origin + chunk.as_vec2() * TILE_SIZE * CHUNK_SIZE as f32;
+            let size = if layer == TileLayer::Background {
+                BRICK_SIZE
+            } else {
+                TILE_SIZE
+            };
+
+            let mut chunk_entity = commands.spawn((
+                MaterialMesh2dBundle {
+                    mesh: meshes.add(quads(&tiles, atlas, origin, size)).into(),
+                    material: material.clone(),
+                    transform: Transform::from_translation(origin.extend(layer.z())),
+                    ..default()
                 },
-                ..Default::default()
-            });
-            y += 32.
+                TileChunk,
+                Name::new(format!("{:?} Chunk {} {}", layer, chunk.x, chunk.y)),
+            ));
+            if matches!(layer, TileLayer::Wall | TileLayer::Obstacle) {
+                chunk_entity.insert(Wall);
+            }
+        }
+    });
+}
+
+// A square of `size` centred on each position, relative to `origin`, each showing its tile from
+// the atlas
+fn quads(tiles: &[(Vec2, usize)], atlas: &TextureAtlas, origin: Vec2, size: f32) -> Mesh {
+    let mut vertices = Vec::with_capacity(tiles.len() * 4);
+    let mut uvs = Vec::with_capacity(tiles.len() * 4);
+    let mut indices = Vec::with_capacity(tiles.len() * 6);
+    for &(position, index) in tiles {
+        let center = position - origin;
+        let half = size / 2.;
+        // Pulled in half a texel so neighbouring tiles in the atlas don't bleed in
+        let rect = atlas.textures[index].inset(-0.5);
+        let (min, max) = (rect.min / atlas.size, rect.max / atlas.size);
+        let first = vertices.len() as u32;
+        for (corner, uv) in [
+            (Vec2::new(-half, -half), [min.x, max.y]),
+            (Vec2::new(half, -half), [max.x, max.y]),
+            (Vec2::new(half, half), [max.x, min.y]),
+            (Vec2::new(-half, half), [min.x, min.y]),
+        ] {
+            vertices.push((center + corner).extend(0.).to_array());
+            uvs.push(uv);
         }
-        y = -size_y / 2.;
-        x += 32.
-    }}
\ No newline at end of file
+        indices.extend([first, first + 1, first + 2, first, first + 2, first + 3]);
+    }
+
+    let normals = vec![[0., 0., 1.]; vertices.len()];
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, vertices);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+    mesh.set_indices(Some(Indices::U32(indices)));
+    mesh
+}
@@ -0,0 +1,300 @@
+use bevy::prelude::*;
+use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+
+use crate::tile_grid::{TileGrid, TileKind};
+
+// Pixels along each side of a tile in the atlas
+const TILE_PX: usize = 32;
+// Width in pixels of the shading along an exposed edge
+const EDGE_PX: usize = 3;
+const ATLAS_COLUMNS: usize = 17;
+
+// Where each kind of tile starts in the atlas
+// 256 walls, one for each combination of open neighbours, see `NEIGHBOURS`
+const WALL: usize = 0;
+// 4 walls next to a door, one for each side the door can be on
+const DOOR_FRAME: usize = WALL + 256;
+// 16 pits, one for each combination of orthogonal neighbours that are solid ground
+const PIT: usize = DOOR_FRAME + 4;
+const FLOOR: usize = PIT + 16;
+const FLOOR_VARIANTS: usize = 2;
+const OBSTACLE: usize = FLOOR + FLOOR_VARIANTS;
+const OBSTACLE_VARIANTS: usize = 2;
+pub const BRICK: usize = OBSTACLE + OBSTACLE_VARIANTS;
+const TILE_COUNT: usize = BRICK + 1;
+
+// Neighbour offsets, in the order of their bits in a tile's neighbour mask. The first four are
+// the orthogonal ones
+const NEIGHBOURS: [IVec2; 8] = [
+    IVec2::new(0, 1),
+    IVec2::new(1, 0),
+    IVec2::new(0, -1),
+    IVec2::new(-1, 0),
+    IVec2::new(1, 1),
+    IVec2::new(1, -1),
+    IVec2::new(-1, -1),
+    IVec2::new(-1, 1),
+];
+
+pub struct TilesetPlugin;
+
+impl Plugin for TilesetPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, load_tileset_sources)
+            .add_systems(Update, build_tileset);
+    }
+}
+
+// Every tile variant the level is drawn with, packed into one atlas when the game starts
+#[derive(Resource)]
+pub struct Tileset {
+    pub atlas: Handle<TextureAtlas>,
+}
+
+// Atlas index to draw `cell` with, picked from its kind and its neighbours
+pub fn tile_index(grid: &TileGrid, cell: IVec2) -> Option<usize> {
+    let kind = grid.get(cell);
+    // Outside the grid counts as more of the same, so nothing gets an edge along the border
+    let neighbour = |i: usize| {
+        let next = cell + NEIGHBOURS[i];
+        if grid.contains(next) {
+            grid.get(next)
+        } else {
+            kind
+        }
+    };
+    let mask = |count: usize, open: &dyn Fn(TileKind) -> bool| {
+        (0..count).fold(0, |mask, i| mask | (open(neighbour(i)) as usize) << i)
+    };
+    let variant = (cell.x.wrapping_mul(73_856_093) ^ cell.y.wrapping_mul(19_349_663)) as u32;
+
+    match kind {
+        TileKind::Empty => None,
+        TileKind::Floor | TileKind::Door | TileKind::LockedDoor => {
+            Some(FLOOR + variant as usize % FLOOR_VARIANTS)
+        }
+        TileKind::Obstacle => Some(OBSTACLE + variant as usize % OBSTACLE_VARIANTS),
+        TileKind::Pit => Some(PIT + mask(4, &|kind| kind != TileKind::Pit)),
+        TileKind::Wall => {
+            let door =
+                (0..4).find(|&i| matches!(neighbour(i), TileKind::Door | TileKind::LockedDoor));
+            match door {
+                Some(side) => Some(DOOR_FRAME + side),
+                None => Some(WALL + mask(8, &|kind| kind != TileKind::Wall)),
+            }
+        }
+    }
+}
+
+#[derive(Resource)]
+struct TilesetSources {
+    wall: Handle<Image>,
+    floor: Handle<Image>,
+    floor2: Handle<Image>,
+    wood: Handle<Image>,
+    sand: Handle<Image>,
+    brick: Handle<Image>,
+}
+
+fn load_tileset_sources(mut commands: Commands, assets: Res<AssetServer>) {
+    commands.insert_resource(TilesetSources {
+        wall: assets.load("wall.png"),
+        floor: assets.load("floor.png"),
+        floor2: assets.load("floor2.png"),
+        wood: assets.load("wood.png"),
+        sand: assets.load("sand.png"),
+        brick: assets.load("brick.png"),
+    });
+}
+
+// Waits for the source images, then draws every variant into the atlas
+fn build_tileset(
+    mut commands: Commands,
+    sources: Option<Res<TilesetSources>>,
+    tileset: Option<Res<Tileset>>,
+    mut images: ResMut<Assets<Image>>,
+    mut atlases: ResMut<Assets<TextureAtlas>>,
+) {
+    if tileset.is_some() {
+        return;
+    }
+    let Some(sources) = sources else {
+        return;
+    };
+    let load = |handle: &Handle<Image>| images.get(handle).and_then(TilePixels::from_image);
+    let (Some(wall), Some(floor), Some(floor2), Some(wood), Some(sand), Some(brick)) = (
+        load(&sources.wall),
+        load(&sources.floor),
+        load(&sources.floor2),
+        load(&sources.wood),
+        load(&sources.sand),
+        load(&sources.brick),
+    ) else {
+        return;
+    };
+
+    let mut tiles = Vec::with_capacity(TILE_COUNT);
+    tiles.extend((0..256).map(|mask| wall_tile(&wall, mask)));
+    tiles.extend((0..4).map(|side| door_frame_tile(&wall, &sand, side)));
+    tiles.extend((0..16).map(pit_tile));
+    tiles.extend([floor, floor2]);
+    tiles.extend([wood.flipped(), wood]);
+    tiles.push(brick);
+    debug_assert_eq!(tiles.len(), TILE_COUNT);
+
+    let rows = TILE_COUNT.div_ceil(ATLAS_COLUMNS);
+    let (width, height) = (ATLAS_COLUMNS * TILE_PX, rows * TILE_PX);
+    let mut data = vec![0; width * height * 4];
+    for (index, tile) in tiles.iter().enumerate() {
+        let (column, row) = (index % ATLAS_COLUMNS, index / ATLAS_COLUMNS);
+        for y in 0..TILE_PX {
+            for x in 0..TILE_PX {
+                let pixel = ((row * TILE_PX + y) * width + column * TILE_PX + x) * 4;
+                data[pixel..pixel + 4].copy_from_slice(&tile.get(x, y));
+            }
+        }
+    }
+
+    let image = Image::new(
+        Extent3d {
+            width: width as u32,
+            height: height as u32,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+    );
+    let atlas = TextureAtlas::from_grid(
+        images.add(image),
+        Vec2::splat(TILE_PX as f32),
+        ATLAS_COLUMNS,
+        rows,
+        None,
+        None,
+    );
+    commands.insert_resource(Tileset {
+        atlas: atlases.add(atlas),
+    });
+}
+
+// One tile's worth of RGBA pixels, top row first
+#[derive(Clone)]
+struct TilePixels(Vec<[u8; 4]>);
+
+impl TilePixels {
+    fn filled(color: [u8; 4]) -> Self {
+        TilePixels(vec![color; TILE_PX * TILE_PX])
+    }
+
+    // Scales any image to the tile size, nearest neighbour
+    fn from_image(image: &Image) -> Option<Self> {
+        let image = image.clone().try_into_dynamic().ok()?.to_rgba8();
+        let (width, height) = (image.width() as usize, image.height() as usize);
+        let mut tile = TilePixels::filled([0; 4]);
+        for y in 0..TILE_PX {
+            for x in 0..TILE_PX {
+                let source =
+                    image.get_pixel((x * width / TILE_PX) as u32, (y * height / TILE_PX) as u32);
+                tile.set(x, y, source.0);
+            }
+        }
+        Some(tile)
+    }
+
+    fn get(&self, x: usize, y: usize) -> [u8; 4] {
+        self.0[y * TILE_PX + x]
+    }
+
+    fn set(&mut self, x: usize, y: usize, pixel: [u8; 4]) {
+        self.0[y * TILE_PX + x] = pixel;
+    }
+
+    // Brightens or darkens the pixels in a rectangle, keeping alpha
+    fn shade(&mut self, x: std::ops::Range<usize>, y: std::ops::Range<usize>, amount: f32) {
+        for y in y {
+            for x in x.clone() {
+                let [r, g, b, a] = self.get(x, y);
+                let scale = |c: u8| (c as f32 * amount).min(255.) as u8;
+                self.set(x, y, [scale(r), scale(g), scale(b), a]);
+            }
+        }
+    }
+
+    fn flipped(&self) -> Self {
+        let mut tile = self.clone();
+        for y in 0..TILE_PX {
+            for x in 0..TILE_PX {
+                tile.set(x, y, self.get(TILE_PX - 1 - x, y));
+            }
+        }
+        tile
+    }
+}
+
+// Pixel ranges along each side of a tile, in `NEIGHBOURS` order
+fn side_ranges(side: usize, width: usize) -> (std::ops::Range<usize>, std::ops::Range<usize>) {
+    match side {
+        0 => (0..TILE_PX, 0..width),
+        1 => (TILE_PX - width..TILE_PX, 0..TILE_PX),
+        2 => (0..TILE_PX, TILE_PX - width..TILE_PX),
+        _ => (0..width, 0..TILE_PX),
+    }
+}
+
+// Lit along the top where the wall faces open floor, in shadow along the bottom and sides, and
+// with a dark notch in each inside corner
+fn wall_tile(wall: &TilePixels, mask: usize) -> TilePixels {
+    let mut tile = wall.clone();
+    let open = |i: usize| mask & (1 << i) != 0;
+    for side in 0..4 {
+        if open(side) {
+            let (x, y) = side_ranges(side, EDGE_PX);
+            let amount = match side {
+                0 => 1.4,
+                2 => 0.55,
+                _ => 0.75,
+            };
+            tile.shade(x, y, amount);
+        }
+    }
+    // Diagonals in `NEIGHBOURS` sit between orthogonal neighbours i and i + 1
+    for corner in 4..8 {
+        let (a, b) = (corner - 4, (corner - 3) % 4);
+        if open(corner) && !open(a) && !open(b) {
+            let (x, _) = side_ranges(if corner == 4 || corner == 5 { 1 } else { 3 }, EDGE_PX);
+            let (_, y) = side_ranges(if corner == 4 || corner == 7 { 0 } else { 2 }, EDGE_PX);
+            tile.shade(x, y, 0.6);
+        }
+    }
+    tile
+}
+
+// A wall with a sand coloured post down the side the door is on
+fn door_frame_tile(wall: &TilePixels, sand: &TilePixels, side: usize) -> TilePixels {
+    let mut tile = wall.clone();
+    let (xs, ys) = side_ranges(side, EDGE_PX * 2);
+    for y in ys.clone() {
+        for x in xs.clone() {
+            tile.set(x, y, sand.get(x, y));
+        }
+    }
+    tile.shade(xs, ys, 0.8);
+    tile
+}
+
+// A dark hole with a lighter lip along each side that borders solid ground
+fn pit_tile(mask: usize) -> TilePixels {
+    let mut tile = TilePixels::filled([13, 13, 13, 255]);
+    for side in 0..4 {
+        if mask & (1 << side) != 0 {
+            let (xs, ys) = side_ranges(side, EDGE_PX);
+            for y in ys {
+                for x in xs.clone() {
+                    tile.set(x, y, [60, 55, 50, 255]);
+                }
+            }
+        }
+    }
+    tile
+}
@@ -1,8 +1,21 @@
 use bevy::prelude::*;
 
 use crate::Money;
+use crate::controls::{Action, ActionInput};
+use crate::floor::DungeonFloor;
+use crate::fog::RoomVisibility;
+use crate::layout::RoomKind;
+use crate::map_gen::{CorridorFloor, RoomTag};
 use crate::player::Player;
 use crate::main_menu::GameState;
+use crate::weapon::Weapon;
+
+// Height of the minimap in the corner of the screen, in pixels
+const MINIMAP_CORNER_HEIGHT: f32 = 108.0;
+// Height of the full-screen map, as a percentage of the window height
+const MINIMAP_FULL_HEIGHT: f32 = 80.0;
+const MINIMAP_DOOR_SIZE: f32 = 3.0;
+const MINIMAP_PLAYER_SIZE: f32 = 5.0;
 
 pub struct GameUI;
 
@@ -12,10 +25,45 @@ pub struct MoneyText;
 #[derive(Component)]
 pub struct PlayerText;
 
+#[derive(Component)]
+pub struct FloorText;
+
+#[derive(Component)]
+pub struct WeaponText;
+
+// Whether the map is a small overlay in the corner or covers most of the screen
+#[derive(Resource, Default, Clone, Copy, PartialEq, Eq)]
+pub enum MinimapView {
+    #[default]
+    Corner,
+    FullScreen,
+}
+
+// Screen-sized node that places the minimap
+#[derive(Component)]
+pub struct MinimapRoot;
+
+// The map itself. Rooms, doors and the player are its children, positioned as a percentage of
+// `bounds`, the area covered by every room on the floor
+#[derive(Component, Default)]
+pub struct Minimap {
+    pub bounds: Rect,
+}
+
+#[derive(Component)]
+pub struct MinimapPlayer;
+
 impl Plugin for GameUI {
     fn build(&self, app: &mut App) {
-        app.add_systems(OnEnter(GameState::Game), spawn_game_ui)
-            .add_systems(Update, (update_money_ui,update_health_ui).run_if(in_state(GameState::Game)));
+        app.init_resource::<MinimapView>()
+            .add_systems(OnEnter(GameState::Game), (spawn_game_ui, spawn_minimap))
+            .add_systems(Update, (update_money_ui,update_health_ui,update_floor_ui,update_weapon_ui).run_if(in_state(GameState::Game)))
+            .add_systems(
+                Update,
+                (toggle_minimap, layout_minimap, draw_minimap, update_minimap_player)
+                    .chain()
+                    .run_if(in_state(GameState::Game)),
+            );
     }
 }
 
@@ -63,6 +111,44 @@ fn spawn_game_ui(mut commands: Commands) {
                 },
                 PlayerText,
             ));
+        })
+        .with_children(|commands| {
+            commands.spawn((
+                TextBundle {
+                    text: Text::from_section(
+                        "Floor!",
+                        TextStyle {
+                            font_size: 32.0,
+                            ..default()
+                        },
+                    ),
+                    style: Style {
+                        margin: UiRect::left(Val::Px(20.0)),
+                        ..default()
+                    },
+                    ..default()
+                },
+                FloorText,
+            ));
+        })
+        .with_children(|commands| {
+            commands.spawn((
+                TextBundle {
+                    text: Text::from_section(
+                        "Weapon!",
+                        TextStyle {
+                            font_size: 32.0,
+                            ..default()
+                        },
+                    ),
+                    style: Style {
+                        margin: UiRect::left(Val::Px(20.0)),
+                        ..default()
+                    },
+                    ..default()
+                },
+                WeaponText,
+            ));
         });
 }
 
@@ -78,4 +164,252 @@ fn update_health_ui(mut texts: Query<&mut Text, With<PlayerText>>, player:Query<
         text.sections[0].value = format!("Health: {:?} HP",player.health );
     }
 
-}
\ No newline at end of file
+}
+
+fn update_floor_ui(mut texts: Query<&mut Text, With<FloorText>>, floor: Res<DungeonFloor>) {
+    for mut text in &mut texts {
+        text.sections[0].value = format!("Floor: {}", floor.0);
+    }
+}
+
+fn update_weapon_ui(
+    mut texts: Query<&mut Text, With<WeaponText>>,
+    weapons: Query<&Weapon, (With<Player>, Changed<Weapon>)>,
+) {
+    let Ok(weapon) = weapons.get_single() else {
+        return;
+    };
+    for mut text in &mut texts {
+        text.sections[0].value = weapon.name.to_string();
+    }
+}
+
+fn spawn_minimap(mut commands: Commands) {
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    padding: UiRect::all(Val::Px(10.0)),
+                    ..default()
+                },
+                ..default()
+            },
+            MinimapRoot,
+            Name::new("Minimap Root"),
+        ))
+        .with_children(|commands| {
+            commands.spawn((
+                NodeBundle {
+                    background_color: Color::rgba(0.0, 0.0, 0.0, 0.6).into(),
+                    ..default()
+                },
+                Minimap::default(),
+                Name::new("Minimap"),
+            ));
+        });
+}
+
+fn toggle_minimap(input: ActionInput, mut view: ResMut<MinimapView>) {
+    if input.just_pressed(Action::ToggleMap) {
+        *view = match *view {
+            MinimapView::Corner => MinimapView::FullScreen,
+            MinimapView::FullScreen => MinimapView::Corner,
+        };
+    }
+}
+
+// The backdrop behind the map, kept apart from the map's own style
+type MinimapRootOnly = (With<MinimapRoot>, Without<Minimap>);
+
+// Moves and resizes the map for the current view, keeping the shape of the floor
+fn layout_minimap(
+    view: Res<MinimapView>,
+    mut roots: Query<(&mut Style, &mut BackgroundColor), MinimapRootOnly>,
+    mut minimaps: Query<(&mut Style, Ref<Minimap>)>,
+) {
+    for (mut style, minimap) in &mut minimaps {
+        if !view.is_changed() && !minimap.is_changed() {
+            continue;
+        }
+        let size = minimap.bounds.size();
+        let aspect = if size.y > 0.0 { size.x / size.y } else { 1.0 };
+        let (width, height) = match *view {
+            MinimapView::Corner => (
+                Val::Px(MINIMAP_CORNER_HEIGHT * aspect),
+                Val::Px(MINIMAP_CORNER_HEIGHT),
+            ),
+            MinimapView::FullScreen => (
+                Val::Vh(MINIMAP_FULL_HEIGHT * aspect),
+                Val::Vh(MINIMAP_FULL_HEIGHT),
+            ),
+        };
+        style.width = width;
+        style.height = height;
+        // Hand-made maps have no rooms to show
+        style.display = if minimap.bounds.is_empty() {
+            Display::None
+        } else {
+            Display::Flex
+        };
+    }
+
+    if !view.is_changed() {
+        return;
+    }
+    for (mut style, mut background) in &mut roots {
+        match *view {
+            MinimapView::Corner => {
+                style.justify_content = JustifyContent::FlexEnd;
+                style.align_items = AlignItems::FlexStart;
+                *background = Color::NONE.into();
+            }
+            MinimapView::FullScreen => {
+                style.justify_content = JustifyContent::Center;
+                style.align_items = AlignItems::Center;
+                *background = Color::rgba(0.0, 0.0, 0.0, 0.5).into();
+            }
+        }
+    }
+}
+
+// Redraws every explored room, its doors and the corridors walked so far whenever a room is
+// entered, a corridor is explored or the floor changes
+fn draw_minimap(
+    mut commands: Commands,
+    mut minimaps: Query<(Entity, &mut Minimap)>,
+    rooms: Query<(&Transform, &RoomTag, &RoomVisibility)>,
+    corridors: Query<Ref<CorridorFloor>>,
+    changed: Query<(), Changed<RoomVisibility>>,
+    mut removed: RemovedComponents<RoomTag>,
+) {
+    let floor_changed = removed.read().count() > 0;
+    let corridor_changed = corridors.iter().any(|floor| floor.is_changed());
+    if changed.is_empty() && !floor_changed && !corridor_changed {
+        return;
+    }
+    let Ok((minimap_entity, mut minimap)) = minimaps.get_single_mut() else {
+        return;
+    };
+
+    let bounds = rooms
+        .iter()
+        .map(|(transform, room, _)| room.rect(transform.translation))
+        .chain(corridors.iter().map(|floor| floor.rect))
+        .reduce(|bounds, rect| bounds.union(rect))
+        .unwrap_or_default();
+    if minimap.bounds != bounds {
+        minimap.bounds = bounds;
+    }
+    // As a percentage across and down the map
+    let to_map = |position: Vec2| {
+        let offset = (position - bounds.min) / bounds.size() * 100.0;
+        Vec2::new(offset.x, 100.0 - offset.y)
+    };
+
+    commands.entity(minimap_entity).despawn_descendants();
+    commands.entity(minimap_entity).with_children(|commands| {
+        // Corridors go first, so the rooms they run into are drawn over their ends
+        for floor in corridors.iter().filter(|floor| floor.explored) {
+            let top_left = to_map(Vec2::new(floor.rect.min.x, floor.rect.max.y));
+            let size = floor.rect.size() / bounds.size() * 100.0;
+            commands.spawn(NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    left: Val::Percent(top_left.x),
+                    top: Val::Percent(top_left.y),
+                    width: Val::Percent(size.x),
+                    height: Val::Percent(size.y),
+                    ..default()
+                },
+                background_color: Color::rgb(0.35, 0.3, 0.25).into(),
+                ..default()
+            });
+        }
+        for (transform, room, visibility) in &rooms {
+            if *visibility == RoomVisibility::Unseen {
+                continue;
+            }
+            let rect = room.rect(transform.translation);
+            let top_left = to_map(Vec2::new(rect.min.x, rect.max.y));
+            let size = rect.size() / bounds.size() * 100.0;
+            let color = match room.kind {
+                RoomKind::Boss => Color::rgb(0.7, 0.1, 0.1),
+                RoomKind::Shop => Color::rgb(0.85, 0.7, 0.2),
+                RoomKind::Treasure => Color::rgb(0.2, 0.5, 0.9),
+                RoomKind::Start => Color::rgb(0.75, 0.75, 0.75),
+                RoomKind::Normal => Color::rgb(0.4, 0.4, 0.4),
+            };
+            let border = if *visibility == RoomVisibility::Current {
+                Color::WHITE
+            } else {
+                Color::rgb(0.15, 0.15, 0.15)
+            };
+            commands.spawn(NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    left: Val::Percent(top_left.x),
+                    top: Val::Percent(top_left.y),
+                    width: Val::Percent(size.x),
+                    height: Val::Percent(size.y),
+                    border: UiRect::all(Val::Px(1.0)),
+                    ..default()
+                },
+                background_color: color.into(),
+                border_color: border.into(),
+                ..default()
+            });
+            for &door in &room.doors {
+                let position = to_map(door);
+                commands.spawn(NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Absolute,
+                        left: Val::Percent(position.x),
+                        top: Val::Percent(position.y),
+                        width: Val::Px(MINIMAP_DOOR_SIZE),
+                        height: Val::Px(MINIMAP_DOOR_SIZE),
+                        margin: UiRect::all(Val::Px(-MINIMAP_DOOR_SIZE / 2.0)),
+                        ..default()
+                    },
+                    background_color: Color::rgb(0.9, 0.8, 0.6).into(),
+                    ..default()
+                });
+            }
+        }
+        commands.spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    width: Val::Px(MINIMAP_PLAYER_SIZE),
+                    height: Val::Px(MINIMAP_PLAYER_SIZE),
+                    margin: UiRect::all(Val::Px(-MINIMAP_PLAYER_SIZE / 2.0)),
+                    ..default()
+                },
+                background_color: Color::GREEN.into(),
+                ..default()
+            },
+            MinimapPlayer,
+        ));
+    });
+}
+
+fn update_minimap_player(
+    minimaps: Query<&Minimap>,
+    players: Query<&Transform, With<Player>>,
+    mut markers: Query<&mut Style, With<MinimapPlayer>>,
+) {
+    let (Ok(minimap), Ok(player)) = (minimaps.get_single(), players.get_single()) else {
+        return;
+    };
+    let bounds = minimap.bounds;
+    if bounds.is_empty() {
+        return;
+    }
+    let offset = (player.translation.xy() - bounds.min) / bounds.size() * 100.0;
+    for mut style in &mut markers {
+        style.left = Val::Percent(offset.x);
+        style.top = Val::Percent(100.0 - offset.y);
+    }
+}
@@ -1,34 +1,103 @@
 use bevy::prelude::*;
 
+use crate::main_menu::GameState;
+use crate::tile_grid::{TileGrid, TileGridBuilt, TileKind};
+use crate::tilemap::TILE_SIZE;
 
 pub struct WallPlugin;
 
 impl Plugin for WallPlugin {
     fn build(&self, app: &mut App) {
-        app
+        app.add_systems(OnEnter(GameState::Game), spawn_wall_parent)
+            .add_systems(
+                Update,
+                (spawn_doorways, update_doorways).run_if(in_state(GameState::Game)),
+            )
             .register_type::<Wall>();
     }
 }
 
+// Marks the chunks walls and obstacles are drawn in
 #[derive(Component, Default, Reflect)]
 #[reflect(Component)]
-pub struct Wall {
+pub struct Wall;
+
+// A tile in the gap a door leaves in a wall. It only blocks anything while its room is locked
+#[derive(Component)]
+pub struct Doorway {
+    pub cell: IVec2,
 }
 
+#[derive(Component)]
+pub struct WallParent;
+
+fn spawn_wall_parent(mut commands: Commands) {
+    commands.spawn((SpatialBundle::default(), WallParent, Name::new("Wall Parent")));
+}
 
-pub fn spawn_wall(
-    commands: &mut Commands,
+pub fn spawn_doorway(
+    commands: &mut ChildBuilder,
     assets: &Res<AssetServer>,
     translation: Vec3,
+    cell: IVec2,
 ) -> Entity {
+    commands
+        .spawn((
+            SpriteBundle {
+                texture: assets.load("sand.png"),
+                sprite: Sprite {
+                    custom_size: Some(Vec2::splat(TILE_SIZE)),
+                    ..default()
+                },
+                transform: Transform::from_translation(translation),
+                ..default()
+            },
+            Doorway { cell },
+        ))
+        .id()
+}
+
+// Doorways stay separate sprites rather than part of the tile chunks, so they can change
+// texture as their room locks and unlocks
+fn spawn_doorways(
+    mut commands: Commands,
+    assets: Res<AssetServer>,
+    grid: Res<TileGrid>,
+    mut built: EventReader<TileGridBuilt>,
+    parent: Query<Entity, With<WallParent>>,
+) {
+    if built.read().count() == 0 {
+        return;
+    }
+    let Ok(parent) = parent.get_single() else {
+        return;
+    };
+
+    commands.entity(parent).with_children(|commands| {
+        for (cell, kind) in grid.cells() {
+            if matches!(kind, TileKind::Door | TileKind::LockedDoor) {
+                let position = grid.grid_to_world(cell);
+                spawn_doorway(commands, &assets, position.extend(10.0), cell);
+            }
+        }
+    });
+}
 
-    let texture = assets.load("wall.png");
-    commands.spawn(SpriteBundle {
-        texture,
-        transform: Transform {
-            translation,
-            ..Default::default()
-        },
-        ..Default::default()
-    }).id()
+// Shows locked doors as wall
+fn update_doorways(
+    grid: Res<TileGrid>,
+    assets: Res<AssetServer>,
+    mut doorways: Query<(&Doorway, &mut Handle<Image>)>,
+) {
+    if !grid.is_changed() {
+        return;
+    }
+    for (doorway, mut texture) in &mut doorways {
+        let path = if grid.get(doorway.cell) == TileKind::LockedDoor {
+            "wall.png"
+        } else {
+            "sand.png"
+        };
+        *texture = assets.load(path);
+    }
 }
@@ -0,0 +1,165 @@
+use bevy::prelude::*;
+use rand::Rng;
+
+use crate::controls::{not_paused, Action, ActionInput};
+use crate::main_menu::GameState;
+use crate::player::Player;
+use crate::projectile::ProjectileModifiers;
+
+pub struct WeaponPlugin;
+
+impl Plugin for WeaponPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            switch_weapon.run_if(in_state(GameState::Game).and_then(not_paused)),
+        )
+            .register_type::<Weapon>();
+    }
+}
+
+// How the player's gun shoots. Every shot fires `projectile_count` projectiles
+#[derive(Component, Clone, Debug, PartialEq, Reflect)]
+#[reflect(Component)]
+pub struct Weapon {
+    // `&'static str` can't be reflected, so these two don't show in the inspector
+    #[reflect(ignore)]
+    pub name: &'static str,
+    // Shots per second
+    pub fire_rate: f32,
+    pub projectile_count: u32,
+    // Degrees. Several projectiles fan out evenly across it, and a lone one strays randomly
+    // within it
+    pub spread: f32,
+    pub speed: f32,
+    pub damage: f32,
+    // How far projectiles fly before disappearing
+    pub range: f32,
+    pub modifiers: ProjectileModifiers,
+    #[reflect(ignore)]
+    pub sprite: &'static str,
+}
+
+impl Default for Weapon {
+    fn default() -> Self {
+        PISTOL
+    }
+}
+
+pub const PISTOL: Weapon = Weapon {
+    name: "Pistol",
+    fire_rate: 4.,
+    projectile_count: 1,
+    spread: 0.,
+    speed: 200.,
+    damage: 25.,
+    range: 800.,
+    modifiers: ProjectileModifiers::NONE,
+    sprite: "bullet2.png",
+};
+
+pub const SHOTGUN: Weapon = Weapon {
+    name: "Shotgun",
+    fire_rate: 1.2,
+    projectile_count: 5,
+    spread: 40.,
+    speed: 180.,
+    damage: 12.,
+    range: 140.,
+    modifiers: ProjectileModifiers::NONE,
+    sprite: "bullet.png",
+};
+
+pub const MACHINE_GUN: Weapon = Weapon {
+    name: "Machine Gun",
+    fire_rate: 12.,
+    projectile_count: 1,
+    spread: 10.,
+    speed: 260.,
+    damage: 8.,
+    range: 300.,
+    modifiers: ProjectileModifiers::NONE,
+    sprite: "bullet.png",
+};
+
+pub const SNIPER: Weapon = Weapon {
+    name: "Sniper",
+    fire_rate: 0.8,
+    projectile_count: 1,
+    spread: 0.,
+    speed: 500.,
+    damage: 100.,
+    range: 1000.,
+    modifiers: ProjectileModifiers {
+        pierce: 2,
+        ricochet: 2,
+        ..ProjectileModifiers::NONE
+    },
+    sprite: "bullet2.png",
+};
+
+pub const LAUNCHER: Weapon = Weapon {
+    name: "Launcher",
+    fire_rate: 1.,
+    projectile_count: 1,
+    spread: 0.,
+    speed: 140.,
+    damage: 20.,
+    range: 400.,
+    modifiers: ProjectileModifiers {
+        explosion_radius: 24.,
+        explosion_damage: 30.,
+        split: 6,
+        ..ProjectileModifiers::NONE
+    },
+    sprite: "bullet2.png",
+};
+
+pub const SEEKER: Weapon = Weapon {
+    name: "Seeker",
+    fire_rate: 3.,
+    projectile_count: 2,
+    spread: 60.,
+    speed: 150.,
+    damage: 15.,
+    range: 500.,
+    modifiers: ProjectileModifiers {
+        homing: 4.,
+        ..ProjectileModifiers::NONE
+    },
+    sprite: "bullet.png",
+};
+
+// Weapons the player cycles through, in order
+pub const WEAPONS: [Weapon; 6] = [PISTOL, SHOTGUN, MACHINE_GUN, SNIPER, LAUNCHER, SEEKER];
+
+impl Weapon {
+    // Directions to fire each projectile in for one shot aimed along `aim`
+    pub fn shot_directions(&self, aim: Vec2, rng: &mut impl Rng) -> Vec<Vec2> {
+        let spread = self.spread.to_radians();
+        if self.projectile_count <= 1 {
+            let stray = if spread > 0. {
+                rng.gen::<f32>() * spread - spread / 2.
+            } else {
+                0.
+            };
+            return vec![Vec2::from_angle(stray).rotate(aim)];
+        }
+        let step = spread / (self.projectile_count - 1) as f32;
+        (0..self.projectile_count)
+            .map(|i| Vec2::from_angle(i as f32 * step - spread / 2.).rotate(aim))
+            .collect()
+    }
+}
+
+fn switch_weapon(input: ActionInput, mut players: Query<&mut Weapon, With<Player>>) {
+    if !input.just_pressed(Action::NextWeapon) {
+        return;
+    }
+    for mut weapon in &mut players {
+        let current = WEAPONS.iter().position(|known| known.name == weapon.name);
+        let next = current.map_or(0, |index| (index + 1) % WEAPONS.len());
+        *weapon = WEAPONS[next].clone();
+        info!("Switched to {}", weapon.name);
+    }
+}